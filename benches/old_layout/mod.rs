@@ -0,0 +1,619 @@
+// The node layout before keys and children moved into separate inline arrays, kept as a reference for the bench:
+// every node holds a `Vec<KeyValue>` of u16 keys and values, and an internal node ends in a `0` sentinel key that
+// points to its last child. Only `get` was added, the rest is the tree as it was.
+#![allow(dead_code)]
+
+use std::mem;
+
+mod slab;
+
+use slab::Slab;
+
+
+// Order used by `BPlusTree::new`. Any order >= MIN_ORDER can be picked at runtime with `BPlusTree::with_order`.
+const BTREE_MAX: usize = 4;
+const MIN_ORDER: usize = 3;
+
+// The root always lives under this id, both while it is a single leaf and after it grows into an internal node.
+const ROOT_ID: u16 = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyValue {
+    pub key: u16,
+    pub value: u16,
+}
+
+#[derive(Clone, Debug)]
+pub enum  NodeType {
+    Internal(Vec<KeyValue>),
+    Leaf(Vec<KeyValue>)
+}
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub node_type: NodeType,
+    pub is_root: bool,
+}
+impl Node {
+    pub fn new(is_root: bool) -> Self {
+        Node{node_type: NodeType::Internal(Vec::new()), is_root}
+    }
+    pub fn get_child(&self, key: u16)-> u16 {
+        match &self.node_type {
+            NodeType::Internal(keys) => keys[self.child_index(key)].value,
+            NodeType::Leaf(_) => panic!("There are no children of leaf nodes"),
+        }
+    }
+
+    // Index of the first cell whose key is bigger than `key`. The last cell is the `0` pointer, so it is left out of the search.
+    fn child_index(&self, key: u16) -> usize {
+        let keys = self.cells();
+        keys[..keys.len() - 1].partition_point(|kv| kv.key <= key)
+    }
+
+    fn cells(&self) -> &Vec<KeyValue> {
+        match &self.node_type {
+            NodeType::Internal(kv) => kv,
+            NodeType::Leaf(kv) => kv,
+        }
+    }
+
+    fn extract(&mut self) -> &mut Vec<KeyValue> {
+        match &mut self.node_type {
+             NodeType::Internal(kv) => kv,
+             NodeType::Leaf(kv) => kv,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self.node_type, NodeType::Leaf(_))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BPlusTree {
+    order: usize,
+    nodes: Slab<Node>
+}
+
+impl Default for BPlusTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BPlusTree {
+    pub fn new() -> Self {
+        Self::with_order(BTREE_MAX)
+    }
+
+    // `order` is the maximum number of keys a leaf can hold; internal nodes hold up to `order + 1` child pointers.
+    pub fn with_order(order: usize) -> Self {
+        assert!(order >= MIN_ORDER, "order must be at least {}", MIN_ORDER);
+        let mut nodes = Slab::new();
+        let root_id = nodes.alloc(Node { node_type: NodeType::Leaf(Vec::new()), is_root: true });
+        debug_assert_eq!(root_id, ROOT_ID);
+        BPlusTree{order, nodes}
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    fn max_key(&self) -> usize {
+        self.order
+    }
+
+    // Rounded up so that a split of `order + 1` keys never leaves a leaf below the minimum.
+    fn min_key(&self) -> usize {
+        self.order.div_ceil(2)
+    }
+
+    fn max_child(&self) -> usize {
+        self.order + 1
+    }
+
+    fn min_child(&self) -> usize {
+        (self.order + 2) / 2
+    }
+    pub fn print_tree(&self, node_key: u16, level: usize) {
+        if let Some(node) = self.nodes.get(node_key) {
+            // Print the current node with indentation
+            let indent = "    ".repeat(level);
+            match &node.node_type {
+                NodeType::Internal(kvs) => {
+                    println!("{}Internal Node (ID: {}):", indent, node_key);
+                    for kv in kvs {
+                        println!("{}  - Key: {}, Points to Node: {}", indent, kv.key, kv.value);
+                        // Recursively print the child nodes
+                        self.print_tree(kv.value, level + 1);
+                    }
+                }
+                NodeType::Leaf(kvs) => {
+                    println!("{}Leaf Node (ID: {}):", indent, node_key);
+                    for kv in kvs {
+                        println!("{}  - Key: {}, Value: {}", indent, kv.key, kv.value);
+                    }
+                }
+            }
+        }
+    }
+
+
+    // Returns (leaf_id, parent_id). A tree that is still a single root leaf returns (0, 0).
+    pub fn search(&self, k: u16) -> (u16, u16){
+        let root = self.nodes.get(ROOT_ID).unwrap();
+        self.search_tree(root, k, ROOT_ID, ROOT_ID)
+    }
+    fn search_tree(&self, node: &Node, key: u16, node_id: u16, parent_id: u16) -> (u16, u16){
+        match &node.node_type {
+            NodeType::Leaf(_) => (node_id, parent_id),
+            NodeType::Internal(_) => {
+                let pointer = node.get_child(key);
+                let child = self.nodes.get(pointer).unwrap();
+                self.search_tree(child, key, pointer, node_id)
+            },
+        }
+    }
+
+
+
+    pub fn get_node(&self, key: u16) -> &Node {
+        let (node_id, _) = self.search(key);
+        self.nodes.get(node_id).unwrap()
+    }
+
+    pub fn mut_node(&mut self, key: u16) -> &mut Node {
+        let (node_id, _) = self.search(key);
+        self.nodes.get_mut(node_id).unwrap()
+    }
+
+    pub fn get(&self, key: u16) -> Option<u16> {
+        let cells = self.get_node(key).cells();
+        cells.binary_search_by_key(&key, |kv| kv.key).ok().map(|i| cells[i].value)
+    }
+
+
+
+    fn is_underflow(&self, node:&Node) -> bool{
+        match &node.node_type {
+            NodeType::Internal(keys) => keys.len() < self.min_child(),
+            NodeType::Leaf(kvs) => kvs.len() < self.min_key(),
+        }
+    }
+
+    fn is_overflow(&self, node:&Node) -> bool{
+        match &node.node_type {
+            NodeType::Internal(keys) => keys.len() > self.max_child(),
+            NodeType::Leaf(kvs) => kvs.len() > self.max_key(),
+        }
+    }
+
+    // Returns false when the key was already present; its value is overwritten in that case.
+    pub fn insert(&mut self, new_kv: KeyValue) -> bool {
+        let mut parents = Vec::new();
+        if !self.insert_recursive(new_kv, ROOT_ID, &mut parents) {
+            return false
+        }
+        while parents.len() > 1 {
+            let node_id = parents.pop().unwrap();
+            if self.is_overflow(self.nodes.get(node_id).unwrap()) {
+                self.split(node_id, parents[parents.len() -1]);
+            }
+        }
+        let root_id = parents.pop().unwrap();
+        if self.is_overflow(self.nodes.get(root_id).unwrap()) {
+            self.split_root(root_id);
+        }
+        true
+    }
+
+    fn insert_recursive(&mut self,new_kv: KeyValue,current: u16, parents: &mut Vec<u16>) -> bool {
+       parents.push(current);
+       let node = self.nodes.get_mut(current).unwrap();
+       match &mut node.node_type {
+        NodeType::Leaf(kvs) => {
+            match kvs.binary_search_by_key(&new_kv.key, |kv| kv.key) {
+                Ok(existing_idx) => {
+                    kvs[existing_idx].value = new_kv.value;
+                    false
+                },
+                Err(insertion_idx) => {
+                    kvs.insert(insertion_idx, new_kv);
+                    true
+                }
+            }
+        },
+        NodeType::Internal(_) => {
+            let next_node_id = node.get_child(new_kv.key);
+            self.insert_recursive(new_kv, next_node_id, parents)
+        }
+       }
+
+    }
+
+    // Moves the root's cells into a fresh child and splits that child, so the root keeps its id.
+    fn split_root(&mut self, root_id: u16) {
+        let root = self.nodes.get_mut(root_id).unwrap();
+        let old_root = mem::replace(&mut root.node_type, NodeType::Internal(Vec::new()));
+        let new_node_id = self.nodes.alloc(Node { node_type: old_root, is_root: false });
+        self.nodes.get_mut(root_id).unwrap().extract().push(KeyValue { key: 0, value: new_node_id });
+        self.split(new_node_id, root_id);
+    }
+
+    // Only the left half is moved out into the new node; the right half stays where it is.
+    fn split(&mut self, current: u16, parent: u16) -> bool {
+        let node = self.nodes.get_mut(current).unwrap();
+        let middle_index = node.cells().len() / 2;
+        let mut new_node_vec: Vec<KeyValue> = node.extract().drain(..middle_index).collect();
+        let new_node_type = match &node.node_type {
+            NodeType::Internal(_) => NodeType::Internal(Vec::new()),
+            NodeType::Leaf(_) => NodeType::Leaf(Vec::new()),
+        };
+        let divider = match &node.node_type {
+            NodeType::Internal(_) => mem::replace(&mut new_node_vec[middle_index - 1].key, 0),
+            NodeType::Leaf(kvs) => kvs[0].key,
+        };
+        let mut new_node = Node { node_type: new_node_type, is_root: false };
+        *new_node.extract() = new_node_vec;
+        let new_node_id = self.nodes.alloc(new_node);
+
+        let parent_node = self.nodes.get_mut(parent).unwrap();
+        match &mut parent_node.node_type {
+          NodeType::Internal(pkvs) => {
+            let current_index = pkvs.iter().position(|kv| kv.value == current).unwrap();
+            pkvs.insert(current_index, KeyValue { key: divider, value: new_node_id });
+          },
+          _ => panic!("No non-internal parent")
+        }
+
+       true
+    }
+
+    pub fn delete(&mut self, key_d: u16) -> bool {
+        let mut parents = Vec::new();
+        let exists = self.delete_recursive(key_d, ROOT_ID, &mut parents);
+        if exists {
+            while parents.len() > 1 {
+                let node_id = parents.pop().unwrap();
+                if self.is_underflow(self.nodes.get(node_id).unwrap()) {
+                    self.distribute_mini(node_id, parents[parents.len() -1]);
+                }
+            }
+
+            self.merge_root(parents.pop().unwrap());
+        }
+        exists
+    }
+
+
+    fn get_sibling(&self , current: u16, parent: u16) -> Vec<KeyValue>{
+       let parent = self.nodes.get(parent).unwrap();
+       match &parent.node_type {
+        NodeType::Internal(pkvs) => {
+            let mut return_vec = Vec::new();
+            let index_current = pkvs.iter().position(|kv| kv.value == current).unwrap_or(0);
+            return_vec.push(KeyValue { key: current, value: index_current as u16});
+            if index_current == 0 {
+                return_vec.push(KeyValue { key: pkvs[1].value, value: 1 });
+            } else {
+                return_vec.push(KeyValue { key: pkvs[index_current -1].value, value: (index_current -1) as u16 });
+            }
+            return_vec
+        },
+        _ => panic!("_+_")
+       }
+    }
+
+    fn delete_recursive(&mut self, key_d: u16, current: u16, parents: &mut Vec<u16>) -> bool {
+        parents.push(current);
+        let node = self.nodes.get_mut(current).unwrap();
+        match &mut node.node_type {
+            NodeType::Leaf(kvs) => {
+                let exists = match kvs.binary_search_by_key(&key_d, |kv| kv.key) {
+                    Ok(i) => {
+                        kvs.remove(i);
+                        true
+                    },
+                    Err(_) => false,
+                };
+                if !exists {
+                    println!("No key found as {}", key_d);
+                }
+
+                exists
+            },
+            NodeType::Internal(_) => {
+                  let next_node_id = node.get_child(key_d);
+                  self.delete_recursive(key_d, next_node_id, parents)
+            }
+       }
+    }
+
+    // Either merges the underflowing node with its sibling into the right-hand node, or moves a single cell across.
+    // Only the cells that change owner are moved; nothing is copied into a scratch buffer.
+    fn distribute_mini(&mut self, current: u16, parent: u16) {
+       let siblings = self.get_sibling(current, parent);
+       let (left, right, left_index) = if siblings[0].value < siblings[1].value {
+           (siblings[0].key, siblings[1].key, siblings[0].value as usize)
+       } else {
+           (siblings[1].key, siblings[0].key, siblings[1].value as usize)
+       };
+       let internal_divider = self.nodes.get(parent).unwrap().cells()[left_index].key;
+       let left_node = self.nodes.get(left).unwrap();
+       let is_leaf = left_node.is_leaf();
+       let total = left_node.cells().len() + self.nodes.get(right).unwrap().cells().len();
+       let capacity = if is_leaf { self.max_key() } else { self.max_child() };
+
+       if total <= capacity {
+           let mut left_node = self.nodes.free(left);
+           let mut cells = mem::take(left_node.extract());
+           if !is_leaf {
+               // The left node's `0` pointer now sits in the middle, so it takes the parent's divider.
+               cells.last_mut().unwrap().key = internal_divider;
+           }
+           let right_cells = self.nodes.get_mut(right).unwrap().extract();
+           right_cells.splice(0..0, cells);
+           match &mut self.nodes.get_mut(parent).unwrap().node_type {
+               NodeType::Internal(pkvs) => {
+                   pkvs.remove(left_index);
+               },
+               _ => panic!("___")
+           }
+       } else if left == current {
+           let right_cells = self.nodes.get_mut(right).unwrap().extract();
+           let mut moved_value = right_cells.remove(0);
+           let new_bound = if is_leaf {
+               right_cells[0].key
+           } else {
+               mem::replace(&mut moved_value.key, 0)
+           };
+           let left_cells = self.nodes.get_mut(left).unwrap().extract();
+           if !is_leaf {
+               left_cells.last_mut().unwrap().key = internal_divider;
+           }
+           left_cells.push(moved_value);
+           match &mut self.nodes.get_mut(parent).unwrap().node_type {
+               NodeType::Internal(pkvs) => {
+                   pkvs[left_index].key = new_bound;
+               },
+               _ => panic!("___")
+           }
+       } else {
+           let left_cells = self.nodes.get_mut(left).unwrap().extract();
+           let mut moved_value = left_cells.pop().unwrap();
+           let new_bound = if is_leaf {
+               moved_value.key
+           } else {
+               moved_value.key = internal_divider;
+               mem::replace(&mut left_cells.last_mut().unwrap().key, 0)
+           };
+           self.nodes.get_mut(right).unwrap().extract().insert(0, moved_value);
+           match &mut self.nodes.get_mut(parent).unwrap().node_type {
+               NodeType::Internal(pkvs) => {
+                   pkvs[left_index].key = new_bound;
+               },
+               _ => panic!("___")
+           }
+       }
+     }
+
+     // Collapses a root with a single child into that child, which may also turn the root back into a leaf.
+     fn merge_root(&mut self, root_id: u16) -> bool{
+        let root = self.nodes.get(root_id).unwrap();
+        let child_id = match &root.node_type {
+         NodeType::Internal(pkvs) => {
+            if pkvs.len() > 1 {
+                return false
+            }
+            pkvs[0].value
+         },
+         NodeType::Leaf(_) => return false,
+        };
+
+        let child = self.nodes.free(child_id);
+        self.nodes.get_mut(root_id).unwrap().node_type = child.node_type;
+        true
+     }
+
+}
+
+/* These function were inspired by https://github.com/antoniosarosi/mkdb/blob/master/src/storage/btree.rs -> SQLLite's B-tree.
+But after some time I realized that this might be a bit more complicated than what I wanted to make so but later on if ever need a better version like this I might come back and finish it.
+// NOT completed, this function will not work as expected do not use it
+    fn merge(&mut self,current: u16, parent: u16) -> bool {
+        let siblings = self.siblings(current, parent);
+        
+        let mut cells = Vec::new();
+ 
+        for  kvs in siblings.clone().into_iter() {
+             let node = self.nodes.get(&kvs.key).unwrap().clone();
+             match &node.node_type {
+                 NodeType::Internal(keychild) => {
+                     for skv in keychild.into_iter() {
+                         
+                         cells.push(skv.clone())
+                     }
+                 },
+                 NodeType::Leaf(keyvalue) => {
+                     for skv in keyvalue.into_iter() {
+                         cells.push(skv.clone())
+                     }
+                 }
+             }
+        }
+        
+        
+        
+        if cells.len() > siblings.len() * MAX_KEY {
+          let new_node_id = self.nodes.len() as u16;
+          let mut is_end = false;
+          let mut plusser = 0;
+          for sibling in siblings.clone().into_iter() {
+             let mut node = self.nodes.get_mut(&sibling.key).unwrap();
+             match &mut node.node_type {
+                 NodeType::Internal(kvs) => {
+                 *kvs = Vec::new();
+                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
+                     kvs.push(cells[i].clone());
+                     plusser += 1;
+                 }
+                 
+                 
+                 let mut parent = self.nodes.get_mut(&parent).unwrap();
+                 match &mut parent.node_type{
+                     NodeType::Internal(pkvs) => {
+                     if pkvs.len() - 1 == sibling.value as usize {
+                         is_end = true
+                     }
+                     pkvs[sibling.value as usize].key = cells[plusser].clone().key
+                     },
+                     _ => panic!("______")
+                 }
+                 self.nodes.insert(new_node_id, Node { node_type: NodeType::Internal(vec![ cells[plusser].clone() ]), is_root: false });
+                 },
+                 NodeType::Leaf(kvs) => {
+                 let mut kvs_index = 0;
+                 *kvs = Vec::new();
+                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
+                     kvs.push(cells[i].clone());
+                     plusser += 1;
+                 }
+                 
+                 let mut parent = self.nodes.get_mut(&parent).unwrap();
+                 match &mut parent.node_type{
+                     NodeType::Internal(pkvs) => {
+                     if pkvs.len() - 1 == sibling.value as usize {
+                         is_end = true
+                     }
+                     pkvs[sibling.value as usize].key = cells[plusser].key
+                     
+                     },
+                     _ => panic!("______")
+                 }  
+ 
+                 self.nodes.insert(new_node_id, Node { node_type: NodeType::Leaf(vec![ cells[plusser].clone() ]), is_root: false });
+                 }
+             }
+          }
+          
+          if is_end {
+             let mut parent = self.nodes.get_mut(&parent).unwrap();
+             match &mut parent.node_type{
+                     NodeType::Internal(pkvs) => {
+                     pkvs.push(KeyValue { key: 0, value: cells[plusser].key })
+                     
+                     },
+                     _ => panic!("______")
+             }
+          } else {
+             let mut parent = self.nodes.get_mut(&parent).unwrap();
+             match &mut parent.node_type{
+                     NodeType::Internal(pkvs) => {
+                     pkvs.insert(siblings[siblings.len() - 1].value as usize + 1,KeyValue { key: cells[plusser].key, value: new_node_id });
+                     
+                     },
+                     _ => panic!("______")
+             }
+          }
+ 
+        } else {
+          let mut plusser = 0;
+          let last_sibling = &siblings[&siblings.len()-1].clone();
+          for sibling in siblings.into_iter() {
+             let mut node = self.nodes.get_mut(&sibling.key).unwrap();
+             match &mut node.node_type {
+                 NodeType::Internal(kvs) => {
+                 *kvs = Vec::new();
+                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
+                     kvs.push(cells[i].clone());
+                     plusser += 1;
+                 }
+                 let mut parent = self.nodes.get_mut(&parent).unwrap();
+                 match &mut parent.node_type{
+                     NodeType::Internal(pkvs) => {
+                     if sibling.value == (pkvs.len() -1) as u16 {
+                         pkvs[sibling.value as usize].key = 0;
+                     }
+                     else {
+                         if last_sibling == &sibling {
+                             continue;
+                          }
+                         pkvs[sibling.value as usize].key = cells[plusser].clone().key
+                     }
+                     },
+                     _ => panic!("______")
+                 }
+                 },
+                 NodeType::Leaf(kvs) => {
+                 *kvs = Vec::new();
+                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
+                     kvs.push(cells[i].clone());
+                     plusser += 1;
+                    
+                 }
+                 let mut parent = self.nodes.get_mut(&parent).unwrap();
+                 match &mut parent.node_type{
+                     NodeType::Internal(pkvs) => {
+                     if sibling.value ==  (pkvs.len() -1) as u16 {
+                         pkvs[sibling.value as usize].key = 0;
+                     }
+                     else {
+                         if last_sibling == &sibling {
+                            continue;
+                         }
+                         pkvs[sibling.value as usize].key = cells[plusser].key
+                     }
+                     },
+                     _ => panic!("______")
+                 }  
+                 }
+             }
+          }
+        }
+ 
+       return true 
+         
+     }
+
+     // NOT used for the working b+tree implementation
+     fn siblings(&mut self, node_id: u16, parent: u16) -> Vec<KeyValue> {
+        let parent_node = self.nodes.get(&parent).unwrap();
+        
+        let mut index;
+        let mut num_siblings = 1;
+        match &parent_node.node_type {
+            NodeType::Internal(keys) => {
+                
+                for i in 0..keys.len() {
+                    if keys[i].value == node_id {
+                        index = i;
+                        if index == 0 || index == keys.len() {
+                           num_siblings *= 2;
+                        }
+
+                        let left_siblings = if index == 0 {
+                            0..0 
+                        } else {
+                             index.saturating_sub(num_siblings)..index
+                        };
+                        let right_siblings = if index == keys.len() - 1 {
+                            index + 1..index + 1 // No right siblings if it's the last node
+                        } else {
+                            (index + 1)..min(index + num_siblings + 1, keys.len())
+                        };
+                        
+                        let get_siblings = |index: usize| KeyValue{key: keys[index].value, value: index as u16};
+                        
+                        return left_siblings
+                                .map(get_siblings)
+                                .chain(std::iter::once(get_siblings(index)))
+                                .chain(right_siblings.map(get_siblings)).collect();
+                    }
+                }
+            },
+            _ => panic!("siblings fn")
+        };
+        
+      return Vec::new();
+    }
+     */
@@ -0,0 +1,57 @@
+// Vec backed node storage. A node id is the index of its slot, so a child hop is a plain index instead of a hash lookup,
+// and ids map one to one onto page numbers once the tree is stored on disk.
+#[derive(Clone, Debug)]
+pub struct Slab<T> {
+    slots: Vec<Option<T>>,
+    // Slots released by `free`, reused before the vector grows.
+    free_list: Vec<u16>,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Slab { slots: Vec::new(), free_list: Vec::new() }
+    }
+
+    pub fn alloc(&mut self, value: T) -> u16 {
+        match self.free_list.pop() {
+            Some(id) => {
+                self.slots[id as usize] = Some(value);
+                id
+            },
+            None => {
+                assert!(self.slots.len() <= u16::MAX as usize, "slab is full");
+                self.slots.push(Some(value));
+                (self.slots.len() - 1) as u16
+            }
+        }
+    }
+
+    pub fn free(&mut self, id: u16) -> T {
+        let value = self.slots[id as usize].take().expect("slot is already free");
+        self.free_list.push(id);
+        value
+    }
+
+    pub fn get(&self, id: u16) -> Option<&T> {
+        self.slots.get(id as usize).and_then(|slot| slot.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: u16) -> Option<&mut T> {
+        self.slots.get_mut(id as usize).and_then(|slot| slot.as_mut())
+    }
+
+    // Number of live slots.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,111 @@
+// Run with `cargo bench`. Times random inserts, lookups and deletes for a few orders and prints the best of a few runs,
+// for the tree and for the old `Vec<KeyValue>` node layout in `old_layout`, so the two can be compared on one machine.
+use std::hint::black_box;
+use std::time::Instant;
+
+use b_plus_tree::btrees::{BPlusTree, KeyValue};
+
+mod old_layout;
+
+// Both layouts get u16 keys and values, the old one has no others, so the timings compare the node layouts and
+// not the width of the keys.
+const KEYS: u16 = 60_000;
+const RUNS: usize = 5;
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn shuffled_keys() -> Vec<u16> {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    let mut keys: Vec<u16> = (1..=KEYS).collect();
+    for i in (1..keys.len()).rev() {
+        let j = (rng.next() % (i as u64 + 1)) as usize;
+        keys.swap(i, j);
+    }
+    keys
+}
+
+fn per_op(start: Instant) -> f64 {
+    start.elapsed().as_nanos() as f64 / KEYS as f64
+}
+
+// Runs the workload on trees whose node capacity `B` fits order `B - 2` exactly.
+fn run<const B: usize>(keys: &[u16]) {
+    let mut best = [f64::MAX; 3];
+    for _ in 0..RUNS {
+        let mut tree = BPlusTree::<u16, u16, B>::default();
+
+        let start = Instant::now();
+        for &key in keys {
+            tree.insert(KeyValue { key, value: key });
+        }
+        best[0] = best[0].min(per_op(start));
+
+        let start = Instant::now();
+        for key in keys {
+            black_box(tree.get(key));
+        }
+        best[1] = best[1].min(per_op(start));
+
+        let start = Instant::now();
+        for key in keys {
+            tree.delete(key);
+        }
+        best[2] = best[2].min(per_op(start));
+    }
+    report("inline", B - 2, best);
+}
+
+// The same workload on the old layout, whose node ids are u16 as well.
+fn run_old(order: usize, keys: &[u16]) {
+    let mut best = [f64::MAX; 3];
+    for _ in 0..RUNS {
+        let mut tree = old_layout::BPlusTree::with_order(order);
+
+        let start = Instant::now();
+        for &key in keys {
+            tree.insert(old_layout::KeyValue { key, value: key });
+        }
+        best[0] = best[0].min(per_op(start));
+
+        let start = Instant::now();
+        for &key in keys {
+            black_box(tree.get(key));
+        }
+        best[1] = best[1].min(per_op(start));
+
+        let start = Instant::now();
+        for &key in keys {
+            tree.delete(key);
+        }
+        best[2] = best[2].min(per_op(start));
+    }
+    report("old", order, best);
+}
+
+fn report(layout: &str, order: usize, best: [f64; 3]) {
+    println!(
+        "{:>6} order {:>4}: insert {:>7.1} ns/op, get {:>7.1} ns/op, delete {:>7.1} ns/op",
+        layout, order, best[0], best[1], best[2]
+    );
+}
+
+fn main() {
+    let keys = shuffled_keys();
+    run::<6>(&keys);
+    run_old(4, &keys);
+    run::<18>(&keys);
+    run_old(16, &keys);
+    run::<66>(&keys);
+    run_old(64, &keys);
+    run::<258>(&keys);
+    run_old(256, &keys);
+}
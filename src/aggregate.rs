@@ -0,0 +1,95 @@
+use std::ops::Add;
+
+// A summary the tree keeps for every subtree, next to the child pointer, so that `aggregate_range` only has to look at
+// the nodes along the two edges of the range. `combine` has to be associative and `identity` neutral to it; it does not
+// have to be commutative, entries are always combined in key order.
+pub trait Aggregate<K, V> {
+    type Value: Clone;
+
+    fn identity() -> Self::Value;
+
+    // The summary of a single entry.
+    fn lift(key: &K, value: &V) -> Self::Value;
+
+    fn combine(a: &Self::Value, b: &Self::Value) -> Self::Value;
+}
+
+// The default: keeps nothing. An aggregate whose value has no size is never stored or updated by the tree.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoAggregate;
+
+impl<K, V> Aggregate<K, V> for NoAggregate {
+    type Value = ();
+
+    fn identity() {}
+
+    fn lift(_: &K, _: &V) {}
+
+    fn combine(_: &(), _: &()) {}
+}
+
+// Sum of the values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sum;
+
+impl<K, V: Clone + Default + Add<Output = V>> Aggregate<K, V> for Sum {
+    type Value = V;
+
+    fn identity() -> V {
+        V::default()
+    }
+
+    fn lift(_: &K, value: &V) -> V {
+        value.clone()
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        a.clone() + b.clone()
+    }
+}
+
+// Smallest value, None for an empty range.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Min;
+
+impl<K, V: Clone + Ord> Aggregate<K, V> for Min {
+    type Value = Option<V>;
+
+    fn identity() -> Option<V> {
+        None
+    }
+
+    fn lift(_: &K, value: &V) -> Option<V> {
+        Some(value.clone())
+    }
+
+    fn combine(a: &Option<V>, b: &Option<V>) -> Option<V> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b).clone()),
+            _ => a.clone().or_else(|| b.clone()),
+        }
+    }
+}
+
+// Biggest value, None for an empty range.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Max;
+
+impl<K, V: Clone + Ord> Aggregate<K, V> for Max {
+    type Value = Option<V>;
+
+    fn identity() -> Option<V> {
+        None
+    }
+
+    fn lift(_: &K, value: &V) -> Option<V> {
+        Some(value.clone())
+    }
+
+    fn combine(a: &Option<V>, b: &Option<V>) -> Option<V> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.max(b).clone()),
+            _ => a.clone().or_else(|| b.clone()),
+        }
+    }
+}
@@ -1,934 +1,2704 @@
-use std::cmp::min;
-use std::{collections::HashMap, u16};
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+use std::iter::{self, Zip};
 use std::mem;
+use std::ops::{Bound, Index, RangeBounds};
 
+use crate::aggregate::{Aggregate, NoAggregate};
+use crate::inline_vec::{self, InlineVec};
+use crate::slab::{NodeId, Slab};
 
+
+// Order used by `BPlusTree::new`. Any order >= MIN_ORDER can be picked at runtime with `BPlusTree::with_order`.
 const BTREE_MAX: usize = 4;
-const MIN_KEY: usize = BTREE_MAX/2;
-const MAX_KEY: usize = BTREE_MAX;
-const MIN_CHILD: usize = (BTREE_MAX+1)/2;
-const MAX_CHILD: usize = BTREE_MAX+1;
+const MIN_ORDER: usize = 3;
+// Node arrays hold one entry more than an internal node's `order + 1` child pointers, for the moment before a split.
+pub const DEFAULT_CAPACITY: usize = BTREE_MAX + 2;
+
+// The root always lives under this id, both while it is a single leaf and after it grows into an internal node.
+const ROOT_ID: NodeId = 0;
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct KeyValue {
-    pub key: u16,
-    pub value: u16,
+pub struct KeyValue<K, V> {
+    pub key: K,
+    pub value: V,
 }
 
+// What a node holds next to its keys. An internal node has one more child than it has keys:
+// child `i` holds the keys that are `>= keys[i - 1]` and `< keys[i]`.
 #[derive(Clone, Debug)]
-pub enum  NodeType {
-    Internal(Vec<KeyValue>),
-    Leaf(Vec<KeyValue>)
+pub enum NodeType<V, const B: usize> {
+    Internal(InlineVec<NodeId, B>),
+    Leaf(InlineVec<V, B>)
+}
+
+// What an internal node stores per child in counted mode. A tree that never counts keeps the default `()`, which
+// takes no room, so its nodes don't carry an array of counts nobody reads; counted mode needs `usize`.
+pub trait Count: Copy + Debug + PartialEq {
+    fn from_size(size: usize) -> Self;
+
+    fn size(self) -> usize;
+}
+
+impl Count for usize {
+    fn from_size(size: usize) -> usize {
+        size
+    }
+
+    fn size(self) -> usize {
+        self
+    }
+}
+
+impl Count for () {
+    fn from_size(_: usize) {}
+
+    fn size(self) -> usize {
+        panic!("a tree without room for counts is never counted")
+    }
 }
+
+// Keys are kept in their own array instead of being interleaved with values or child ids, and both arrays are
+// stored inline, so the binary search during a descent stays inside the node's own cache lines.
 #[derive(Clone, Debug)]
-pub struct Node {
-    pub node_type: NodeType,
+#[repr(C, align(64))]
+pub struct Node<K, V, const B: usize, S = (), C = ()> {
+    pub keys: InlineVec<K, B>,
+    pub node_type: NodeType<V, B>,
+    // Number of entries under each child, stored inline right after the child pointers. Only filled in counted mode,
+    // and with the default `C = ()` it takes no room at all.
+    pub counts: InlineVec<C, B>,
+    // The aggregate of each child's subtree, see `Aggregate`, stored inline as well. Only filled when the tree has one.
+    pub summaries: InlineVec<S, B>,
     pub is_root: bool,
+    // The neighbouring leaves in key order, so scans can move from leaf to leaf in both directions. Unused in internal nodes.
+    pub prev: Option<NodeId>,
+    pub next: Option<NodeId>,
 }
-impl Node {
+impl<K: Ord, V, const B: usize, S, C> Node<K, V, B, S, C> {
     pub fn new(is_root: bool) -> Self {
-        Node{node_type: NodeType::Internal(Vec::new()), is_root: is_root}
+        Node{keys: InlineVec::new(), node_type: NodeType::Internal(InlineVec::new()), is_root, prev: None, next: None, counts: InlineVec::new(), summaries: InlineVec::new()}
+    }
+
+    fn leaf() -> Self {
+        Node{keys: InlineVec::new(), node_type: NodeType::Leaf(InlineVec::new()), is_root: false, prev: None, next: None, counts: InlineVec::new(), summaries: InlineVec::new()}
     }
-    pub fn get_child(&self, key: u16)-> u16 {
+
+    pub fn get_child(&self, key: &K)-> NodeId {
         match &self.node_type {
-            NodeType::Internal(keys) => {
-                for i in 1..keys.len() {
-                    if keys[i].key > key {
-                        return keys[i].value;
-                    }
-                }
-                return keys[0].value;
-            },
+            NodeType::Internal(children) => children[self.child_index(key)],
             NodeType::Leaf(_) => panic!("There are no children of leaf nodes"),
-            _ => panic!("---")
         }
     }
 
-    fn extract(&mut self) -> &mut Vec<KeyValue> {
-        match &mut self.node_type {
-             NodeType::Internal(kv) => {
-                return kv
-             },
-             NodeType::Leaf(kv) => {
-                return kv
-             }
+    // Index of the first key that is bigger than `key`, which is also the index of the child that covers it.
+    fn child_index(&self, key: &K) -> usize {
+        self.keys.partition_point(|k| k <= key)
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self.node_type, NodeType::Leaf(_))
+    }
+
+    // What the fill limits are checked against: child pointers for internal nodes, keys for leaves.
+    fn len(&self) -> usize {
+        match &self.node_type {
+            NodeType::Internal(children) => children.len(),
+            NodeType::Leaf(values) => values.len(),
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct BPlusTree {
-    root: Node,
-    leaf_tree: Node,
-    unique_id: u16,
-    nodes: HashMap<u16, Node>
+// The nodes a descent went through, from the root down to a leaf, each with its index among its parent's children
+// (0 for the root). It replaces the call stack of a recursive descent, so the depth of the tree is not bounded by it,
+// and it can be kept around and refilled by `search_path` without allocating again.
+#[derive(Clone, Debug, Default)]
+pub struct TreePath {
+    steps: Vec<(NodeId, usize)>,
 }
 
-impl BPlusTree {
+impl TreePath {
     pub fn new() -> Self {
-        BPlusTree{root: Node::new(true), leaf_tree: Node { node_type: NodeType::Leaf(Vec::new()), is_root: true },unique_id: 1, nodes: HashMap::new()}
-    }
-    pub fn print_tree(&self, node_key: u16, level: usize) {
-        if self.is_leaf_root() {
-            println!("-> {:?}", self.leaf_tree)
-        }
-        else {
-            if let Some(node) = self.nodes.get(&node_key) {
-                // Print the current node with indentation
-                let indent = "    ".repeat(level);
-                match &node.node_type {
-                    NodeType::Internal(kvs) => {
-                        println!("{}Internal Node (ID: {}):", indent, node_key);
-                        for kv in kvs {
-                            println!("{}  - Key: {}, Points to Node: {}", indent, kv.key, kv.value);
-                            // Recursively print the child nodes
-                            self.print_tree(kv.value, level + 1);
-                        }
-                    }
-                    NodeType::Leaf(kvs) => {
-                        println!("{}Leaf Node (ID: {}):", indent, node_key);
-                        for kv in kvs {
-                            println!("{}  - Key: {}, Value: {}", indent, kv.key, kv.value);
-                        }
-                    }
-                }
-            }
-         }
-       
+        TreePath { steps: Vec::new() }
     }
 
-    
-    pub fn search(&self, k: u16) -> (u16, u16){
-        let root = self.root.clone();
-        self.search_tree(root, k, None, None)
+    pub fn push(&mut self, node: NodeId, index: usize) {
+        self.steps.push((node, index));
     }
-    fn search_tree(&self, node:Node, key: u16, leaf_id: Option<u16>,parent_id: Option<u16>) -> (u16, u16){
-       match node.node_type {
-          NodeType::Leaf(keyvalue) => {
-               return (leaf_id.unwrap(), parent_id.unwrap())
-          },
-          NodeType::Internal(keys) => {
-                  let mut pointer: u16 = keys[keys.len()-1].value;
-                  for i in 0..keys.len()-1{
-                      if key < keys[i].key {
-                        pointer = keys[i].value;
-                        break;
-                      }
-                  };
-                  let child = self.nodes.get(&pointer).unwrap().clone();
-                  let mut parent:u16;
-                  match &child.node_type {
-                      NodeType::Internal(_) => parent = pointer,
-                      NodeType::Leaf(_) => {
-                        if parent_id != None {
-                            parent = parent_id.unwrap();
-                        } else {
-                            parent = 0;
-                        }
-                      }
-                  }
-                  self.search_tree(child, key, Some(pointer), Some(parent))
 
-          },
-          _ => panic!("Undefined structure")
-       }
+    pub fn pop(&mut self) -> Option<(NodeId, usize)> {
+        self.steps.pop()
+    }
+
+    // The deepest node on the path, the leaf right after a descent.
+    pub fn last(&self) -> Option<(NodeId, usize)> {
+        self.steps.last().copied()
     }
 
+    pub fn steps(&self) -> &[(NodeId, usize)] {
+        &self.steps
+    }
 
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
 
-    pub fn get_node(&self, key: u16) -> Node {
-        let (node_id, parent_id) = self.search(key);
-        self.nodes.get(&node_id).unwrap().clone()
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
     }
 
-    pub fn mut_node(&mut self, key: u16) -> &mut Node {
-        let (node_id, parent_id) = self.search(key);
-        self.nodes.get_mut(&node_id).unwrap()
+    pub fn clear(&mut self) {
+        self.steps.clear();
     }
+}
+
+// How a node that underflows after a delete is fixed up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rebalance {
+    // Merge with one sibling, or move a single entry over from it (`distribute_mini`).
+    #[default]
+    TwoNode,
+    // Spread the entries of up to three siblings over as few nodes as they need (`balance_siblings`).
+    Siblings,
+}
 
+// Where `split` cuts an overflowing node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitPolicy {
+    // Always cut in the middle.
+    #[default]
+    Middle,
+    // Cut the rightmost node of every level 90/10, so ascending keys leave full nodes behind instead of half empty ones.
+    Append,
+    // Cut in the middle, unless the last inserts kept appending to the end of the rightmost leaf.
+    Adaptive,
+    // B*-tree splits: shift entries into a sibling with room, and only when both are full turn the two into three
+    // nodes that are each two thirds full. Deletes keep that minimum by spreading three siblings over two nodes. The
+    // root still splits in the middle.
+    BStar,
+}
+
+// When `insert` and `delete` fix nodes that overflow or underflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pass {
+    // Record the path on the way down and fix the nodes on it on the way back up.
+    #[default]
+    BottomUp,
+    // Split full nodes and top up minimal ones on the way down, so an operation is a single pass that never goes back
+    // to an ancestor.
+    TopDown,
+}
 
+// Share of the entries the left node keeps when a rightmost node is split for appends.
+const APPEND_FILL_PERCENT: usize = 90;
+// Consecutive appends to the rightmost leaf after which `SplitPolicy::Adaptive` treats the inserts as sequential.
+const ADAPTIVE_RUN: usize = 4;
+// Share of short leaves, in percent, from which a bulk filter rebuilds the tree instead of fixing each leaf in place.
+const RELOAD_SHORT_PERCENT: usize = 25;
 
-    fn is_underflow(&self, node:&Node) -> bool{
-        match &node.node_type {
-            NodeType::Internal(keys) => {
-                if keys.len() < MIN_CHILD + 1 {
-                    
-                    return true
-                } else {
-                    return false 
+// `B` is the capacity of the inline node arrays and bounds the order: `order + 2 <= B`.
+// `A` is the aggregate kept for every subtree, nothing by default.
+#[derive(Clone)]
+pub struct BPlusTree<K, V, const B: usize = DEFAULT_CAPACITY, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    order: usize,
+    rebalance: Rebalance,
+    split_policy: SplitPolicy,
+    pass: Pass,
+    counted: bool,
+    // Inserts in a row that went to the end of the rightmost leaf, tracked for `SplitPolicy::Adaptive`.
+    append_run: usize,
+    // Number of entries.
+    len: usize,
+    nodes: Slab<Node<K, V, B, A::Value, C>>
+}
+
+// Uses the biggest order the node capacity allows.
+impl<K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Default for BPlusTree<K, V, B, A, C> {
+    fn default() -> Self {
+        Self::with_order(B - 2)
+    }
+}
+
+impl<K: Debug, V: Debug, const B: usize, A: Aggregate<K, V>, C: Count> BPlusTree<K, V, B, A, C> {
+    pub fn print_tree(&self, node_key: NodeId, level: usize) {
+        // Nodes still to print with their level and the line of the parent that points to them. The children of an
+        // internal node are pushed in reverse, so they come off the stack in order.
+        let mut stack = vec![(node_key, level, None)];
+        while let Some((node_key, level, pointer)) = stack.pop() {
+            if let Some(line) = pointer {
+                println!("{}", line);
+            }
+            let Some(node) = self.nodes.get(node_key) else { continue };
+            // Print the current node with indentation
+            let indent = "    ".repeat(level);
+            match &node.node_type {
+                NodeType::Internal(children) => {
+                    println!("{}Internal Node (ID: {}):", indent, node_key);
+                    for (i, child) in children.iter().enumerate().rev() {
+                        let line = match node.keys.get(i) {
+                            Some(key) => format!("{}  - Key: {:?}, Points to Node: {}", indent, key, child),
+                            None => format!("{}  - Rest, Points to Node: {}", indent, child),
+                        };
+                        stack.push((*child, level + 1, Some(line)));
+                    }
                 }
-            },
-            NodeType::Leaf(KeyValue) => {
-                if KeyValue.len() < MIN_KEY {
-                    return true
-                } else {
-                    return false
+                NodeType::Leaf(values) => {
+                    println!("{}Leaf Node (ID: {}):", indent, node_key);
+                    for (key, value) in node.keys.iter().zip(values) {
+                        println!("{}  - Key: {:?}, Value: {:?}", indent, key, value);
+                    }
                 }
             }
         }
     }
+}
 
-    fn is_overflow(&self, node:&Node) -> bool{
-        match &node.node_type {
-            NodeType::Internal(keys) => {
-                if keys.len() > MAX_CHILD {
-                    return true
-                } else {
-                    return false 
-                }
-            },
-            NodeType::Leaf(KeyValue) => {
-                if KeyValue.len() > MAX_KEY {
-                    return true
-                } else {
-                    return false
-                }
+impl<K: Ord + Clone, V> BPlusTree<K, V> {
+    pub fn new() -> Self {
+        Self::with_order(BTREE_MAX)
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> BPlusTree<K, V, B, A, C> {
+    // An aggregate without a value carries nothing, so the tree skips it altogether.
+    const AGGREGATING: bool = mem::size_of::<A::Value>() != 0;
+
+    // `order` is the maximum number of keys a leaf can hold; internal nodes hold up to `order + 1` child pointers.
+    // Orders above the default need a bigger node capacity, e.g. `BPlusTree::<u32, u32, 66>::with_order(64)`.
+    pub fn with_order(order: usize) -> Self {
+        assert!(order >= MIN_ORDER, "order must be at least {}", MIN_ORDER);
+        assert!(order + 2 <= B, "order {} needs a node capacity of at least {}", order, order + 2);
+        BPlusTree{order, rebalance: Rebalance::default(), split_policy: SplitPolicy::default(), pass: Pass::default(), counted: false, append_run: 0, len: 0, nodes: Self::empty_nodes()}
+    }
+
+    // Nodes split under `Append` or `Adaptive` may start out below the minimum fill; the appends that follow fill them up.
+    // Switching to `BStar` raises the minimum without refilling the nodes already there, deletes top up the ones they reach.
+    pub fn set_split_policy(&mut self, split_policy: SplitPolicy) {
+        self.split_policy = split_policy;
+        self.append_run = 0;
+    }
+
+    pub fn set_rebalance(&mut self, rebalance: Rebalance) {
+        self.rebalance = rebalance;
+    }
+
+    // `Pass::TopDown` rounds the minimum fill down, so that a full node can be split before anything is added to it.
+    // Deletes in that mode always top nodes up with `distribute_mini`, whatever `Rebalance` is set to.
+    pub fn set_pass(&mut self, pass: Pass) {
+        self.pass = pass;
+    }
+
+    // Counted mode keeps the size of every child's subtree next to the child pointer, which `rank`, `select` and
+    // `count_range` need. Switching it on counts the whole tree once. The counts need room in the nodes, so the tree
+    // has to be declared with `C = usize`, e.g. `BPlusTree::<u32, u32, 6, NoAggregate, usize>::with_order(4)`.
+    pub fn set_counted(&mut self, counted: bool) {
+        assert!(!counted || mem::size_of::<C>() != 0, "counted mode needs room for the counts, declare the tree with C = usize");
+        self.counted = counted;
+        // Going through the nodes backwards counts children before their parents.
+        for id in self.node_ids().into_iter().rev() {
+            if counted {
+                self.refresh(id);
+            } else {
+                self.nodes.get_mut(id).unwrap().counts.clear();
             }
         }
     }
-    
-    fn is_leaf_root(&self) -> bool {
-        match &self.leaf_tree.node_type {
-            NodeType::Leaf(kvs) => {
-                kvs.len() < MAX_KEY + 1
-            },
-            _ => panic!()
+
+    // Every node id, parents before their children.
+    fn node_ids(&self) -> Vec<NodeId> {
+        let mut ids = Vec::new();
+        let mut stack = vec![ROOT_ID];
+        while let Some(id) = stack.pop() {
+            ids.push(id);
+            if let NodeType::Internal(children) = &self.nodes.get(id).unwrap().node_type {
+                stack.extend(children.iter());
+            }
         }
+        ids
     }
 
-    fn insert_leaf_tree(&mut self, new_kv: KeyValue) {
-       match &mut self.leaf_tree.node_type {
-         NodeType::Leaf(kvs) => {
-            let mut idx = kvs.len();
-            for i in 0..kvs.len(){
-                if new_kv.key < kvs[i].key {
-                    idx = i
-                }
-            }
-            kvs.insert(idx, new_kv);
-            let cells = kvs.clone();
-            if !self.is_leaf_root() {
-                self.root = Node {node_type: NodeType::Internal(vec![KeyValue {key: 0, value: self.unique_id}]), is_root: true};
-                self.nodes.insert(0, self.root.clone());
-                self.nodes.insert(self.unique_id, Node { node_type: NodeType::Leaf(cells), is_root: false });
-                self.unique_id += 1;
-                self.split(self.unique_id -1, 0);
-                self.root = self.nodes.get(&0).unwrap().clone();
-            }
-         },
-         _ => panic!()
-       }
+    pub fn order(&self) -> usize {
+        self.order
     }
-    
-    pub fn insert(&mut self, new_kv: KeyValue) -> bool {
-        if self.is_leaf_root() {
-            self.insert_leaf_tree(new_kv);
-            return true;
-        }
-        let root = self.root.clone();
-        let mut parents = vec![0];
-        let mut next_node_id;
-        match root.node_type {
-            NodeType::Internal(pkvs) => {
-              next_node_id = pkvs[pkvs.len() -1].value;
-              for i in 0..pkvs.len() - 1 {
-                  if  new_kv.key < pkvs[i].key {
-                      next_node_id = pkvs[i].value;
-                      break;
-                  }
-              }
-            },
-            _ => panic!("__")
-        }
-        self.insert_recursive(new_kv, next_node_id, &mut parents);
-        while parents.len() > 1 {
-            let node_id = parents.pop().unwrap();
-            if self.is_overflow(self.nodes.get(&node_id).unwrap()) {
-                self.split(node_id, parents[parents.len() -1]);
-            } 
-        }
-        let root_id = parents.pop().unwrap();
-        if self.is_overflow(self.nodes.get(&root_id).unwrap()) {
-            self.split_root(root_id);
-        }
-        self.root = self.nodes.get(&0).unwrap().clone();
-        return true
 
+    pub fn len(&self) -> usize {
+        self.len
     }
 
-    fn insert_recursive(&mut self,new_kv: KeyValue,current: u16, parents: &mut Vec<u16>){
-       let mut node = self.nodes.get_mut(&current).unwrap();
-       match &mut node.node_type {
-        NodeType::Leaf(kvs) => {
-            let mut insertion_idx = kvs.len();
-            for i in 0..kvs.len() {
-                if new_kv.key < kvs[i].key {
-                   insertion_idx = i;
-                   break;
-                }
-            }
-            kvs.insert(insertion_idx, new_kv);
-            parents.push(current);
-            
-        },
-        NodeType::Internal(kvs) => {
-            parents.push(current);
-            let mut next_node_id;
-            next_node_id = kvs[kvs.len() -1].value;
-            for i in 0..kvs.len() - 1 {
-                if  new_kv.key < kvs[i].key {
-                    next_node_id = kvs[i].value;
-                    break;
-                }
-            }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 
-            self.insert_recursive(new_kv, next_node_id, parents)
-        }
-       }
+    // Drops every entry and keeps the settings.
+    pub fn clear(&mut self) {
+        self.nodes = Self::empty_nodes();
+        self.len = 0;
+        self.append_run = 0;
+    }
 
+    fn max_key(&self) -> usize {
+        self.order
     }
 
-    fn split_root(&mut self, root_id: u16) {
-        let new_node_id = self.unique_id;
-        let root = self.nodes.get_mut(&root_id).unwrap();
-        let pkvs = root.extract();
-        let new_node = Node {node_type: NodeType::Internal(mem::replace(pkvs, Vec::new())), is_root: false};
-        pkvs.push(KeyValue { key: 0, value: new_node_id});
-        self.nodes.insert(new_node_id, new_node);
-        self.unique_id += 1;
-        self.split(new_node_id, root_id);
+    // Rounded up so that a split of `order + 1` keys never leaves a leaf below the minimum. Top-down splits happen at
+    // `order` keys instead, so that mode rounds down. B*-tree splits keep nodes two thirds full, see `two_thirds_min`.
+    fn min_key(&self) -> usize {
+        if self.two_thirds_min() {
+            return (2 * self.max_key() + 1) / 3
+        }
+        match self.pass {
+            Pass::BottomUp => self.order.div_ceil(2),
+            Pass::TopDown => self.order / 2,
+        }
     }
-    
-    fn split(&mut self, current: u16, parent: u16) -> bool {
-        let node = self.nodes.get(&current).unwrap();
-        let mut cells: Vec<KeyValue> = Vec::new();
-        let mut is_internal = false;
-        match &node.node_type {
-          NodeType::Internal(kvs) => {
-            cells = kvs.clone();
-            is_internal = true;
-          },
-          NodeType::Leaf(kvs) => {
-            cells = kvs.clone();
-          }
-        };
 
-        let middle_index = cells.len() / 2;
+    fn max_child(&self) -> usize {
+        self.order + 1
+    }
 
-        let new_node_id = self.unique_id;
-        let mut new_node_vec: Vec<KeyValue> = Vec::new();
-        for i in 0..middle_index {
-            new_node_vec.push(cells[i].clone())
+    fn min_child(&self) -> usize {
+        if self.two_thirds_min() {
+            return (2 * self.max_child() + 1) / 3
         }
-        let mut divider = cells[middle_index].key;
-        if is_internal {
-            divider = new_node_vec[middle_index-1].key
+        match self.pass {
+            Pass::BottomUp => (self.order + 2) / 2,
+            Pass::TopDown => self.order.div_ceil(2),
         }
-        if is_internal{
-            new_node_vec[middle_index-1].key = 0;
+    }
+
+    // `SplitPolicy::BStar` raises the minimum to two thirds of a node, which its three-way splits leave behind and
+    // which deletes keep by merging three siblings into two. Only the root's split still cuts in the middle, so while
+    // the root has two children these may be down to half full. At order 3 that leaves an internal child with just two
+    // children, too few to keep their own siblings two thirds full, so order 3 stays at half. The top-down pass tops
+    // nodes up one entry at a time and keeps its own minimum as well.
+    fn two_thirds_min(&self) -> bool {
+        self.split_policy == SplitPolicy::BStar && self.pass == Pass::BottomUp && self.order > MIN_ORDER
+    }
+
+    // Returns (leaf_id, parent_id). A tree that is still a single root leaf returns (0, 0).
+    pub fn search(&self, k: &K) -> (NodeId, NodeId){
+        let (mut node_id, mut parent_id) = (ROOT_ID, ROOT_ID);
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(_) => return (node_id, parent_id),
+                NodeType::Internal(_) => {
+                    parent_id = node_id;
+                    node_id = node.get_child(k);
+                },
+            }
         }
-        let  node = self.nodes.get_mut(&current).unwrap();
-        match &mut node.node_type {
-            NodeType::Internal(kvs) => {
-                for i in 0..middle_index {
-                    kvs.remove(0);
-                }
-                self.nodes.insert(new_node_id, Node { node_type: NodeType::Internal(new_node_vec), is_root: false });
-                self.unique_id += 1;
-            },
-            NodeType::Leaf(kvs) => {
-                for i in 0..middle_index {
-                    kvs.remove(0);
-                }
-                self.nodes.insert(new_node_id, Node { node_type: NodeType::Leaf(new_node_vec), is_root: false });
-                self.unique_id += 1;
+    }
+
+    // Refills `path` with every node from the root down to the leaf that covers `key`, and returns that leaf.
+    pub fn search_path(&self, key: &K, path: &mut TreePath) -> NodeId {
+        path.clear();
+        let (mut node_id, mut index) = (ROOT_ID, 0);
+        loop {
+            path.push(node_id, index);
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(_) => return node_id,
+                NodeType::Internal(children) => {
+                    index = node.child_index(key);
+                    node_id = children[index];
+                },
             }
-        };
+        }
+    }
 
-        
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let node = self.get_node(key);
+        match &node.node_type {
+            NodeType::Leaf(values) => node.keys.binary_search(key).ok().map(|i| &values[i]),
+            NodeType::Internal(_) => panic!("search ended on an internal node"),
+        }
+    }
 
-        let mut parent_node = self.nodes.get_mut(&parent).unwrap();
-        match &mut parent_node.node_type {
-          NodeType::Internal(pkvs) => {
-            let mut current_index: usize = 0;
-            for i in 0..pkvs.len() {
-               if &pkvs[i].value == &current {
-                  current_index = i;  
-               }   
-            };
+    // The place of `key` in the tree, for looking at it and then inserting or changing it without a second descent.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, B, A, C> {
+        let mut path = TreePath::new();
+        let leaf = self.search_path(&key, &mut path);
+        match self.nodes.get(leaf).unwrap().keys.binary_search(&key) {
+            Ok(index) => Entry::Occupied(OccupiedEntry { tree: self, path, index }),
+            Err(index) => Entry::Vacant(VacantEntry { tree: self, key, path, index }),
+        }
+    }
 
-            pkvs.insert(current_index, KeyValue { key: divider, value: new_node_id });
+    // All entries in key order, from either end.
+    pub fn iter(&self) -> Iter<'_, K, V, B, A, C> {
+        self.range(..)
+    }
 
-          },
-          _ => panic!("No non-internal parent")
+    // The entries whose keys fall in `range`, from either end, e.g. `tree.range(..k).rev()` walks down from below `k`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V, B, A, C> {
+        let (front, back) = self.range_positions(&range).unzip();
+        Iter { tree: self, front, back }
+    }
+
+    // The first and the last entry in `range`, None when it holds nothing.
+    fn range_positions<R: RangeBounds<K>>(&self, range: &R) -> Option<((NodeId, usize), (NodeId, usize))> {
+        let front = match range.start_bound() {
+            Bound::Included(key) => self.lower_bound(key, false),
+            Bound::Excluded(key) => self.lower_bound(key, true),
+            Bound::Unbounded => self.position_at(self.first_leaf(), 0),
+        };
+        let back = match range.end_bound() {
+            Bound::Included(key) => self.upper_bound(key, true),
+            Bound::Excluded(key) => self.upper_bound(key, false),
+            Bound::Unbounded => self.last_position(),
+        };
+        // The two ends have crossed when the range is empty or reversed.
+        match (front, back) {
+            (Some(f), Some(b)) if self.entry_at(f).0 <= self.entry_at(b).0 => Some((f, b)),
+            _ => None,
         }
-       
-       
+    }
 
+    // The entry with the largest key <= `key`. Like the other nearest key lookups, it takes the last or first entry of
+    // the neighbouring leaf when the leaf `key` leads to has no answer.
+    pub fn floor(&self, key: &K) -> Option<(&K, &V)> {
+        self.upper_bound(key, true).map(|position| self.entry_at(position))
+    }
 
-       return  true
+    // The entry with the smallest key >= `key`.
+    pub fn ceiling(&self, key: &K) -> Option<(&K, &V)> {
+        self.lower_bound(key, false).map(|position| self.entry_at(position))
     }
 
-    pub fn delete(&mut self, key_d: u16) -> bool {
-        let root = self.root.clone();
-        let mut parents = vec![0];
-        let mut next_node_id;
-        match root.node_type {
-            NodeType::Internal(pkvs) => {
-              next_node_id = pkvs[pkvs.len() -1].value;
-              for i in 0..pkvs.len() - 1 {
-                  if  key_d < pkvs[i].key {
-                      next_node_id = pkvs[i].value;
-                      break;
-                  }
-              }
-            },
-            _ => panic!("__")
-        }
-        let exists = self.delete_recursive(key_d, next_node_id, &mut parents);
-        if exists {
-            while parents.len() > 1 {
-                let node_id = parents.pop().unwrap();
-                if self.is_underflow(self.nodes.get(&node_id).unwrap()) {
-                    self.distribute_mini(node_id, parents[parents.len() -1]);
-                } 
-            }
+    // The entry with the largest key < `key`.
+    pub fn predecessor(&self, key: &K) -> Option<(&K, &V)> {
+        self.upper_bound(key, false).map(|position| self.entry_at(position))
+    }
 
-            self.merge_root(parents.pop().unwrap());
-        }
-        self.root = self.nodes.get(&0).unwrap().clone();
-        return true
+    // The entry with the smallest key > `key`.
+    pub fn successor(&self, key: &K) -> Option<(&K, &V)> {
+        self.lower_bound(key, true).map(|position| self.entry_at(position))
+    }
 
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.position_at(self.first_leaf(), 0).map(|position| self.entry_at(position))
     }
 
-    
-    fn get_sibling(&self , current: u16, parent: u16) -> Vec<KeyValue>{
-       let parent = self.nodes.get(&parent).unwrap();
-       match &parent.node_type {
-        NodeType::Internal(pkvs) => {
-            let mut return_vec = Vec::new();
-            let mut index_current = 0;
-            for i in 0..pkvs.len() {
-                if current == pkvs[i].value {
-                   index_current = i;
-                   break;
-                }
-            }
-            return_vec.push(KeyValue { key: current, value: index_current as u16});
-            if index_current == 0 {
-                return_vec.push(KeyValue { key: pkvs[1].value, value: 1 });
-            } else {
-                return_vec.push(KeyValue { key: pkvs[index_current -1].value, value: (index_current -1) as u16 });
-            }
-            return return_vec;
-        },
-        _ => panic!("_+_")
-       } 
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.last_position().map(|position| self.entry_at(position))
     }
 
-    fn delete_recursive(&mut self, key_d: u16, current: u16, parents: &mut Vec<u16>) -> bool {
-        let mut node = self.nodes.get_mut(&current).unwrap();
-        match &mut node.node_type {
-            NodeType::Leaf(kvs) => {
-                let mut exists = false;
-                for i in 0..kvs.len() {
-                    if key_d == kvs[i].key {
-                       kvs.remove(i);
-                       parents.push(current); 
-                       exists= true;
-                       break;
+    // Number of keys smaller than `key`. Needs counted mode, like `select` and `count_range`.
+    pub fn rank(&self, key: &K) -> usize {
+        self.count_below(key, false)
+    }
+
+    // The entry at position `i` in key order, counting from 0.
+    pub fn select(&self, mut i: usize) -> Option<(&K, &V)> {
+        assert!(self.counted, "select needs counted mode, see set_counted");
+        let mut node_id = ROOT_ID;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(values) => return (i < values.len()).then(|| (&node.keys[i], &values[i])),
+                NodeType::Internal(children) => {
+                    let mut index = 0;
+                    while i >= node.counts[index].size() {
+                        i -= node.counts[index].size();
+                        index += 1;
+                        if index == children.len() {
+                            return None
+                        }
                     }
-                }
-                if !exists {
-                    println!("No key found as {}", key_d);
-                }
+                    node_id = children[index];
+                },
+            }
+        }
+    }
 
-                return exists;
-            
-            },
-            NodeType::Internal(kvs) => {
-                  parents.push(current);
-                  let mut next_node_id;
-                  next_node_id = kvs[kvs.len() -1].value;
-                  for i in 0..kvs.len() - 1 {
-                     if  key_d < kvs[i].key {
-                         next_node_id = kvs[i].value;
-                         break;
-                     }
-                  }
-                  self.delete_recursive(key_d, next_node_id, parents)
+    // Number of keys that fall in `range`.
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R) -> usize {
+        assert!(self.counted, "count_range needs counted mode, see set_counted");
+        let start = match range.start_bound() {
+            Bound::Included(key) => self.count_below(key, false),
+            Bound::Excluded(key) => self.count_below(key, true),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => self.count_below(key, true),
+            Bound::Excluded(key) => self.count_below(key, false),
+            Bound::Unbounded => self.subtree_size(ROOT_ID),
+        };
+        end.saturating_sub(start)
+    }
+
+    // Number of keys < `key`, or <= `key` when `inclusive`, adding up the counts left of the path down.
+    fn count_below(&self, key: &K, inclusive: bool) -> usize {
+        assert!(self.counted, "order statistics need counted mode, see set_counted");
+        let mut count = 0;
+        let mut node_id = ROOT_ID;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(_) => {
+                    let below = if inclusive { node.keys.partition_point(|k| k <= key) } else { node.keys.partition_point(|k| k < key) };
+                    return count + below
+                },
+                NodeType::Internal(children) => {
+                    let index = node.child_index(key);
+                    count += node.counts[..index].iter().map(|count| count.size()).sum::<usize>();
+                    node_id = children[index];
+                },
             }
-       }
+        }
     }
 
-    fn distribute_mini(&mut self, current: u16, parent: u16) {
-       let siblings = self.get_sibling(current, parent);
-       
-       let mut cells: Vec<KeyValue>= Vec::new();
-       let mut reverse = false;
-       let mut internal_divider: u16  = 0;
-       if siblings[0].value < siblings[1].value {
-          let node1 = self.nodes.get(&siblings[0].key).unwrap();
-          let node2 = self.nodes.get(&siblings[1].key).unwrap();
-          match &node1.node_type {
-            NodeType::Internal(kvs) => {
-                cells.extend(kvs.iter().cloned());
-                let parent = self.nodes.get(&parent).unwrap();
-                match &parent.node_type {
-                    NodeType::Internal(pkvs) => {
-                        internal_divider = pkvs[siblings[0].value as usize].key;
-                    },
-                    _ => panic!("-")
-                }
-            },
-            NodeType::Leaf(kvs) => {
-                cells.extend(kvs.iter().cloned());
-            },  
-          };
-          match &node2.node_type {
-            NodeType::Internal(kvs) => {
-                cells.extend(kvs.iter().cloned());
-            },
-            NodeType::Leaf(kvs) => {
-                cells.extend(kvs.iter().cloned());
-            },
+    // Combines the entries whose keys fall in `range`, in key order. Only the nodes along the two edges of the range are
+    // visited, everything between the edges is covered by the aggregates their parents keep.
+    pub fn aggregate_range<R: RangeBounds<K>>(&self, range: R) -> A::Value {
+        if !Self::AGGREGATING {
+            return A::identity()
         }
-       } else {
-            reverse = true;
-            let node1 = self.nodes.get(&siblings[0].key).unwrap();
-            let node2 = self.nodes.get(&siblings[1].key).unwrap();
-            match &node2.node_type {
-                NodeType::Internal(kvs) => {
-                    cells.extend(kvs.iter().cloned());
-                    let parent = self.nodes.get(&parent).unwrap();
-                    match &parent.node_type {
-                        NodeType::Internal(pkvs) => {
-                            internal_divider = pkvs[siblings[1].value as usize].key;
-                        },
-                        _ => panic!("-")
+        let (start, end) = (range.start_bound(), range.end_bound());
+        // Go down together while both edges lead to the same child.
+        let mut node_id = ROOT_ID;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(values) => {
+                    let (from, to) = (start_index(&node.keys, start), end_index(&node.keys, end));
+                    if from >= to {
+                        return A::identity()
                     }
-                    
+                    return Self::fold_entries(&node.keys[from..to], &values[from..to])
                 },
-                NodeType::Leaf(kvs) => {
-                    cells.extend(kvs.iter().cloned());
-                },  
-            };
-            match &node1.node_type {
-                NodeType::Internal(kvs) => {
-                    cells.extend(kvs.iter().cloned());
+                NodeType::Internal(children) => {
+                    let (from, to) = (start_child(node, start), end_child(node, end));
+                    if from == to {
+                        node_id = children[from];
+                        continue;
+                    }
+                    if from > to {
+                        return A::identity()
+                    }
+                    let left = self.aggregate_from(children[from], start);
+                    let middle = Self::fold_summaries(&node.summaries[from + 1..to]);
+                    let right = self.aggregate_to(children[to], end);
+                    return A::combine(&A::combine(&left, &middle), &right)
+                },
+            }
+        }
+    }
+
+    // Aggregate of the entries under `node_id` that are after `start`.
+    fn aggregate_from(&self, mut node_id: NodeId, start: Bound<&K>) -> A::Value {
+        // The children right of the edge, per level. They come after everything deeper down.
+        let mut rights = Vec::new();
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(values) => {
+                    let from = start_index(&node.keys, start);
+                    let edge = Self::fold_entries(&node.keys[from..], &values[from..]);
+                    return rights.iter().rev().fold(edge, |acc, right| A::combine(&acc, right))
                 },
-                NodeType::Leaf(kvs) => {
-                    cells.extend(kvs.iter().cloned());
+                NodeType::Internal(children) => {
+                    let index = start_child(node, start);
+                    rights.push(Self::fold_summaries(&node.summaries[index + 1..]));
+                    node_id = children[index];
                 },
             }
-       }
-       
-       
-       
-       if reverse {
-        let node2 = self.nodes.get_mut(&siblings[1].key).unwrap();
-        match &mut node2.node_type {
-           NodeType::Leaf(kvs) => {
-             if cells.len() <= MAX_KEY {
-                let node1 = self.nodes.get_mut(&siblings[0].key).unwrap();
-                 match &mut node1.node_type {
-                     NodeType::Leaf(kvs1) => {
-                        let _ = mem::replace(kvs1, cells);
-                     },
-                     _ => panic!("___")
-                 }
-                 self.nodes.remove(&siblings[1].key);
-                 let parent_node = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent_node.node_type {
-                     NodeType::Internal(pkvs) => {
-                         pkvs.remove(siblings[1].value as usize);
-                     },
-                     _ => panic!("___")
-                 }
-             } else {
-                 let mut moved_value = kvs.pop().unwrap();
-                 let last_idx = &kvs.len() -1;
-                 let new_bound = moved_value.key.clone();
-                 let node1 = self.nodes.get_mut(&siblings[0].key).unwrap();
-                 match &mut node1.node_type {
-                     NodeType::Leaf(kvs1) => {
-                         kvs1.insert(0,moved_value);
-                     },
-                     _ => panic!("___")
-                 }
-                 let parent_node = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent_node.node_type {
-                     NodeType::Internal(pkvs) => {
-                         pkvs[siblings[1].value as usize].key = new_bound;
-                     },
-                     _ => panic!("___")
-                 }
-             }
-           },
-           NodeType::Internal(kvs) => {
-             if cells.len() <= MAX_CHILD {
-                 for i in 0..cells.len() {
-                     if cells[i].key == 0 {
-                         cells[i].key = internal_divider;
-                         break;
-        
-                     }
-                 }
-                 let node1 = self.nodes.get_mut(&siblings[0].key).unwrap();
-                 match &mut node1.node_type {
-                     NodeType::Internal(kvs1) => {
-                        let _ = mem::replace(kvs1, cells);
-                     },
-                     _ => panic!("{:?}", node1)
-                 }
-                 self.nodes.remove(&siblings[1].key);
-                 let parent_node = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent_node.node_type {
-                     NodeType::Internal(pkvs) => {
-                         pkvs.remove(siblings[1].value as usize);
-                     },
-                     _ => panic!("___")
-                 }
-                 
-             } else {
-                 let mut moved_value = kvs.pop().unwrap();
-                 let last_idx = &kvs.len() -1;
-                 let new_bound = kvs[last_idx].key.clone();
-                 kvs[last_idx].key = 0;
-                 moved_value.key = internal_divider;
-                 let node1 = self.nodes.get_mut(&siblings[0].key).unwrap();
-                 match &mut node1.node_type {
-                     NodeType::Internal(kvs1) => {
-                         kvs1.insert(0,moved_value);
-                     },
-                     _ => panic!("___")
-                 }
-                 let parent_node = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent_node.node_type {
-                     NodeType::Internal(pkvs) => {
-                         pkvs[siblings[1].value as usize].key = new_bound;
-                     },
-                     _ => panic!("___")
-                 }
-             }
-           }
         }
-       } else {
-           let node2 = self.nodes.get_mut(&siblings[1].key).unwrap();
-           match &mut node2.node_type {
-              NodeType::Leaf(kvs) => {
-                if cells.len() <= MAX_KEY {
-                    let _ = mem::replace(kvs, cells);
-                    self.nodes.remove(&siblings[0].key);
-                    let parent_node = self.nodes.get_mut(&parent).unwrap();
-                    match &mut parent_node.node_type {
-                        NodeType::Internal(pkvs) => {
-                            pkvs.remove(siblings[0].value as usize);
-                        },
-                        _ => panic!("___")
-                    }
-                } else {
-                    let moved_value = kvs.remove(0);
-                    let new_bound = kvs[0].key.clone();
-                    let node1 = self.nodes.get_mut(&siblings[0].key).unwrap();
-                    match &mut node1.node_type {
-                        NodeType::Leaf(kvs1) => {
-                            kvs1.push(moved_value);
-                        },
-                        _ => panic!("___")
-                    }
-                    let parent_node = self.nodes.get_mut(&parent).unwrap();
-                    match &mut parent_node.node_type {
-                        NodeType::Internal(pkvs) => {
-                            pkvs[siblings[0].value as usize].key = new_bound;
-                        },
-                        _ => panic!("___")
-                    }
-                }
-              },
-              NodeType::Internal(kvs) => {
-                if cells.len() <= MAX_CHILD {
-                    for i in 0..cells.len() {
-                        if cells[i].key == 0 {
-                            
-                            cells[i].key = internal_divider;
-                            break;
-           
-                        }
-                    }
-                    let _ = mem::replace(kvs, cells);
-                    self.nodes.remove(&siblings[0].key);
-                    let parent_node = self.nodes.get_mut(&parent).unwrap();
-                    match &mut parent_node.node_type {
-                        NodeType::Internal(pkvs) => {
-                            pkvs.remove(siblings[0].value as usize);
-                            
-                        },
-                        _ => panic!("___")
-                    }
-                    
-                } else {
-                    let mut moved_value = kvs.remove(0);
-                    let new_bound = moved_value.key.clone();
-                    moved_value.key = 0;
-                    let node1 = self.nodes.get_mut(&siblings[0].key).unwrap();
-                    match &mut node1.node_type {
-                        NodeType::Internal(kvs1) => {
-                            let last_idx = &kvs1.len() -1;
-                            kvs1[last_idx].key = internal_divider;
-                            kvs1.push(moved_value);
-                        },
-                        _ => panic!("___")
-                    }
-                    let parent_node = self.nodes.get_mut(&parent).unwrap();
-                    match &mut parent_node.node_type {
-                        NodeType::Internal(pkvs) => {
-                            println!("{:?}", pkvs);
-                            pkvs[siblings[0].value as usize].key = new_bound;
-                        },
-                        _ => panic!("___")
-                    }
-                }
-              }
-           }
-       }
-       
-       
-     }
-       
-     fn merge_root(&mut self, root_id: u16) -> bool{
-        let root = self.nodes.get(&root_id).unwrap();
-        let mut child_id = 0;
-        let mut cells: Vec<KeyValue> = Vec::new();
-        match &root.node_type {
-         NodeType::Internal(pkvs) => {
-            if pkvs.len() > 1 {
-                return false
+    }
+
+    // Aggregate of the entries under `node_id` that are before `end`.
+    fn aggregate_to(&self, mut node_id: NodeId, end: Bound<&K>) -> A::Value {
+        let mut acc = A::identity();
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(values) => {
+                    let to = end_index(&node.keys, end);
+                    return A::combine(&acc, &Self::fold_entries(&node.keys[..to], &values[..to]))
+                },
+                NodeType::Internal(children) => {
+                    let index = end_child(node, end);
+                    acc = A::combine(&acc, &Self::fold_summaries(&node.summaries[..index]));
+                    node_id = children[index];
+                },
             }
-             child_id = pkvs[0].value;
-         },
-         _ => panic!("__")
-        };
-        match &self.nodes.get(&child_id).unwrap().node_type {
-          NodeType::Internal(kvs) => {
-             cells.extend(kvs.clone().into_iter())
-          },
-          NodeType::Leaf(kvs) => {
-             todo!()
-          }
-        };
- 
-        self.nodes.remove(&child_id);
- 
-        let root = self.nodes.get_mut(&root_id).unwrap();
-        match &mut root.node_type {
-          NodeType::Internal(pkvs) => {
-             mem::replace(pkvs, cells);
-          },
-          _ => panic!("{{{{}}}}")
         }
-        true
-     }
+    }
 
-}
+    // The first entry with a key >= `key`, or > `key` when `exclusive`.
+    fn lower_bound(&self, key: &K, exclusive: bool) -> Option<(NodeId, usize)> {
+        let (leaf, _) = self.search(key);
+        let keys = &self.nodes.get(leaf).unwrap().keys;
+        let index = if exclusive { keys.partition_point(|k| k <= key) } else { keys.partition_point(|k| k < key) };
+        self.position_at(leaf, index)
+    }
 
-/* These function were inspired by https://github.com/antoniosarosi/mkdb/blob/master/src/storage/btree.rs -> SQLLite's B-tree.
-But after some time I realized that this might be a bit more complicated than what I wanted to make so but later on if ever need a better version like this I might come back and finish it.
-// NOT completed, this function will not work as expected do not use it
-    fn merge(&mut self,current: u16, parent: u16) -> bool {
-        let siblings = self.siblings(current, parent);
-        
-        let mut cells = Vec::new();
- 
-        for  kvs in siblings.clone().into_iter() {
-             let node = self.nodes.get(&kvs.key).unwrap().clone();
-             match &node.node_type {
-                 NodeType::Internal(keychild) => {
-                     for skv in keychild.into_iter() {
-                         
-                         cells.push(skv.clone())
-                     }
-                 },
-                 NodeType::Leaf(keyvalue) => {
-                     for skv in keyvalue.into_iter() {
-                         cells.push(skv.clone())
-                     }
-                 }
-             }
-        }
-        
-        
-        
-        if cells.len() > siblings.len() * MAX_KEY {
-          let new_node_id = self.nodes.len() as u16;
-          let mut is_end = false;
-          let mut plusser = 0;
-          for sibling in siblings.clone().into_iter() {
-             let mut node = self.nodes.get_mut(&sibling.key).unwrap();
-             match &mut node.node_type {
-                 NodeType::Internal(kvs) => {
-                 *kvs = Vec::new();
-                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
-                     kvs.push(cells[i].clone());
-                     plusser += 1;
-                 }
-                 
-                 
-                 let mut parent = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent.node_type{
-                     NodeType::Internal(pkvs) => {
-                     if pkvs.len() - 1 == sibling.value as usize {
-                         is_end = true
-                     }
-                     pkvs[sibling.value as usize].key = cells[plusser].clone().key
-                     },
-                     _ => panic!("______")
-                 }
-                 self.nodes.insert(new_node_id, Node { node_type: NodeType::Internal(vec![ cells[plusser].clone() ]), is_root: false });
-                 },
-                 NodeType::Leaf(kvs) => {
-                 let mut kvs_index = 0;
-                 *kvs = Vec::new();
-                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
-                     kvs.push(cells[i].clone());
-                     plusser += 1;
-                 }
-                 
-                 let mut parent = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent.node_type{
-                     NodeType::Internal(pkvs) => {
-                     if pkvs.len() - 1 == sibling.value as usize {
-                         is_end = true
-                     }
-                     pkvs[sibling.value as usize].key = cells[plusser].key
-                     
-                     },
-                     _ => panic!("______")
-                 }  
- 
-                 self.nodes.insert(new_node_id, Node { node_type: NodeType::Leaf(vec![ cells[plusser].clone() ]), is_root: false });
-                 }
-             }
-          }
-          
-          if is_end {
-             let mut parent = self.nodes.get_mut(&parent).unwrap();
-             match &mut parent.node_type{
-                     NodeType::Internal(pkvs) => {
-                     pkvs.push(KeyValue { key: 0, value: cells[plusser].key })
-                     
-                     },
-                     _ => panic!("______")
-             }
-          } else {
-             let mut parent = self.nodes.get_mut(&parent).unwrap();
-             match &mut parent.node_type{
-                     NodeType::Internal(pkvs) => {
-                     pkvs.insert(siblings[siblings.len() - 1].value as usize + 1,KeyValue { key: cells[plusser].key, value: new_node_id });
-                     
-                     },
-                     _ => panic!("______")
-             }
-          }
- 
+    // The last entry with a key <= `key`, or < `key` when not `inclusive`.
+    fn upper_bound(&self, key: &K, inclusive: bool) -> Option<(NodeId, usize)> {
+        let (leaf, _) = self.search(key);
+        let keys = &self.nodes.get(leaf).unwrap().keys;
+        let index = if inclusive { keys.partition_point(|k| k <= key) } else { keys.partition_point(|k| k < key) };
+        match index {
+            0 => self.position_before(leaf),
+            _ => Some((leaf, index - 1)),
+        }
+    }
+
+    fn last_position(&self) -> Option<(NodeId, usize)> {
+        let leaf = self.last_leaf();
+        match self.nodes.get(leaf).unwrap().len() {
+            0 => None,
+            len => Some((leaf, len - 1)),
+        }
+    }
+
+    // A cursor on the smallest entry, see `Cursor`.
+    pub fn cursor(&mut self) -> Cursor<'_, K, V, B, A, C> {
+        let mut cursor = Cursor { tree: self, position: None, changed: false };
+        cursor.seek_first();
+        cursor
+    }
+
+    // The leftmost and rightmost leaves, reached by always taking the first or the last child.
+    fn first_leaf(&self) -> NodeId {
+        let mut node_id = ROOT_ID;
+        while let NodeType::Internal(children) = &self.nodes.get(node_id).unwrap().node_type {
+            node_id = children[0];
+        }
+        node_id
+    }
+
+    fn last_leaf(&self) -> NodeId {
+        let mut node_id = ROOT_ID;
+        while let NodeType::Internal(children) = &self.nodes.get(node_id).unwrap().node_type {
+            node_id = children[children.len() - 1];
+        }
+        node_id
+    }
+
+    // The entry at `index` of `leaf`, or the first one of the next leaf when `index` is past the end.
+    // Only the root can be an empty leaf, so the next leaf always has a first entry.
+    fn position_at(&self, leaf: NodeId, index: usize) -> Option<(NodeId, usize)> {
+        let node = self.nodes.get(leaf).unwrap();
+        if index < node.len() {
+            Some((leaf, index))
         } else {
-          let mut plusser = 0;
-          let last_sibling = &siblings[&siblings.len()-1].clone();
-          for sibling in siblings.into_iter() {
-             let mut node = self.nodes.get_mut(&sibling.key).unwrap();
-             match &mut node.node_type {
-                 NodeType::Internal(kvs) => {
-                 *kvs = Vec::new();
-                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
-                     kvs.push(cells[i].clone());
-                     plusser += 1;
-                 }
-                 let mut parent = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent.node_type{
-                     NodeType::Internal(pkvs) => {
-                     if sibling.value == (pkvs.len() -1) as u16 {
-                         pkvs[sibling.value as usize].key = 0;
-                     }
-                     else {
-                         if last_sibling == &sibling {
-                             continue;
-                          }
-                         pkvs[sibling.value as usize].key = cells[plusser].clone().key
-                     }
-                     },
-                     _ => panic!("______")
-                 }
-                 },
-                 NodeType::Leaf(kvs) => {
-                 *kvs = Vec::new();
-                 for i in  (sibling.value * 4 ) as usize..min((((sibling.value +1) * 4)) as usize, cells.len()){ 
-                     kvs.push(cells[i].clone());
-                     plusser += 1;
-                    
-                 }
-                 let mut parent = self.nodes.get_mut(&parent).unwrap();
-                 match &mut parent.node_type{
-                     NodeType::Internal(pkvs) => {
-                     if sibling.value ==  (pkvs.len() -1) as u16 {
-                         pkvs[sibling.value as usize].key = 0;
-                     }
-                     else {
-                         if last_sibling == &sibling {
-                            continue;
-                         }
-                         pkvs[sibling.value as usize].key = cells[plusser].key
-                     }
-                     },
-                     _ => panic!("______")
-                 }  
-                 }
-             }
-          }
-        }
- 
-       return true 
-         
-     }
+            node.next.map(|next| (next, 0))
+        }
+    }
 
-     // NOT used for the working b+tree implementation
-     fn siblings(&mut self, node_id: u16, parent: u16) -> Vec<KeyValue> {
-        let parent_node = self.nodes.get(&parent).unwrap();
-        
-        let mut index;
-        let mut num_siblings = 1;
-        match &parent_node.node_type {
-            NodeType::Internal(keys) => {
-                
-                for i in 0..keys.len() {
-                    if keys[i].value == node_id {
-                        index = i;
-                        if index == 0 || index == keys.len() {
-                           num_siblings *= 2;
-                        }
+    // The last entry of the leaf before `leaf`.
+    fn position_before(&self, leaf: NodeId) -> Option<(NodeId, usize)> {
+        let prev = self.nodes.get(leaf).unwrap().prev?;
+        Some((prev, self.nodes.get(prev).unwrap().len() - 1))
+    }
 
-                        let left_siblings = if index == 0 {
-                            0..0 
-                        } else {
-                             index.saturating_sub(num_siblings)..index
-                        };
-                        let right_siblings = if index == keys.len() - 1 {
-                            index + 1..index + 1 // No right siblings if it's the last node
-                        } else {
-                            (index + 1)..min(index + num_siblings + 1, keys.len())
-                        };
-                        
-                        let get_siblings = |index: usize| KeyValue{key: keys[index].value, value: index as u16};
-                        
-                        return left_siblings
-                                .map(get_siblings)
-                                .chain(std::iter::once(get_siblings(index)))
-                                .chain(right_siblings.map(get_siblings)).collect();
-                    }
-                }
-            },
-            _ => panic!("siblings fn")
-        };
-        
-      return Vec::new();
+    fn entry_at(&self, (leaf, index): (NodeId, usize)) -> (&K, &V) {
+        let node = self.nodes.get(leaf).unwrap();
+        match &node.node_type {
+            NodeType::Leaf(values) => (&node.keys[index], &values[index]),
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        }
+    }
+
+    pub fn get_node(&self, key: &K) -> &Node<K, V, B, A::Value, C> {
+        let (node_id, _) = self.search(key);
+        self.nodes.get(node_id).unwrap()
+    }
+
+    pub fn mut_node(&mut self, key: &K) -> &mut Node<K, V, B, A::Value, C> {
+        let (node_id, _) = self.search(key);
+        self.nodes.get_mut(node_id).unwrap()
+    }
+
+
+
+    fn is_underflow(&self, node: &Node<K, V, B, A::Value, C>) -> bool{
+        match &node.node_type {
+            NodeType::Internal(children) => children.len() < self.min_child(),
+            NodeType::Leaf(values) => values.len() < self.min_key(),
+        }
     }
-     */
\ No newline at end of file
+
+    // `is_underflow` for a child of `parent`, with the exception `two_thirds_min` makes for the two children of the root.
+    fn is_short(&self, node: &Node<K, V, B, A::Value, C>, parent: NodeId) -> bool {
+        if !self.is_underflow(node) {
+            return false
+        }
+        let root_pair = match &self.nodes.get(parent).unwrap().node_type {
+            NodeType::Internal(children) => self.two_thirds_min() && parent == ROOT_ID && children.len() == 2,
+            NodeType::Leaf(_) => false,
+        };
+        let half = if node.is_leaf() { self.order.div_ceil(2) } else { (self.order + 2) / 2 };
+        !root_pair || node.len() < half
+    }
+
+    fn is_overflow(&self, node: &Node<K, V, B, A::Value, C>) -> bool{
+        match &node.node_type {
+            NodeType::Internal(children) => children.len() > self.max_child(),
+            NodeType::Leaf(values) => values.len() > self.max_key(),
+        }
+    }
+
+    // Top-down mode splits a node that is full before the descent enters it.
+    fn is_full(&self, node: &Node<K, V, B, A::Value, C>) -> bool{
+        match &node.node_type {
+            NodeType::Internal(children) => children.len() >= self.max_child(),
+            NodeType::Leaf(values) => values.len() >= self.max_key(),
+        }
+    }
+
+    // Top-down mode tops up a node that could not lose an entry before the descent enters it.
+    fn is_minimal(&self, node: &Node<K, V, B, A::Value, C>) -> bool{
+        match &node.node_type {
+            NodeType::Internal(children) => children.len() <= self.min_child(),
+            NodeType::Leaf(values) => values.len() <= self.min_key(),
+        }
+    }
+
+    // Returns false when the key was already present; its value is overwritten in that case.
+    pub fn insert(&mut self, new_kv: KeyValue<K, V>) -> bool {
+        if self.pass == Pass::TopDown {
+            return self.insert_top_down(new_kv)
+        }
+        let mut path = TreePath::new();
+        let leaf = self.search_path(&new_kv.key, &mut path);
+        let Some(position) = self.insert_into_leaf(leaf, new_kv) else {
+            self.refresh_summaries(&path);
+            return false
+        };
+        self.split_path(path, position);
+        true
+    }
+
+    // Fixes the nodes on `path` from the leaf up after a new entry went into the leaf at its end, at `position`.
+    fn split_path(&mut self, mut path: TreePath, position: usize) {
+        let (leaf, _) = path.last().unwrap();
+        self.len += 1;
+        let rightmost = match self.split_policy {
+            SplitPolicy::Append | SplitPolicy::Adaptive => self.rightmost_prefix(&path),
+            _ => 0,
+        };
+        self.track_appends(leaf, position, rightmost == path.len());
+        while path.len() > 1 {
+            let (node_id, index) = path.pop().unwrap();
+            let (parent, _) = path.last().unwrap();
+            if self.is_overflow(self.nodes.get(node_id).unwrap()) {
+                match self.split_policy {
+                    SplitPolicy::BStar => self.split_bstar(node_id, parent, 0),
+                    // `node_id` sat at depth `path.len()` of the path.
+                    _ => { self.split(node_id, parent, path.len() < rightmost); },
+                }
+            } else {
+                self.add_count(parent, index, 1);
+                self.refresh_summary(parent, index);
+            }
+        }
+        let (root_id, _) = path.pop().unwrap();
+        if self.is_overflow(self.nodes.get(root_id).unwrap()) {
+            self.split_root(root_id);
+        }
+    }
+
+    // Single pass insert. A full node is split while its parent is at hand, before the descent enters it, so the parent
+    // always has room for one more divider and nothing is left to fix afterwards.
+    fn insert_top_down(&mut self, new_kv: KeyValue<K, V>) -> bool {
+        // Counted mode bumps the counts on the way down, so it has to know up front whether the key is new.
+        let grows = self.counted && self.get(&new_kv.key).is_none();
+        // Aggregates depend on the new value, so they are refreshed on a second pass up the path afterwards.
+        let key = Self::AGGREGATING.then(|| new_kv.key.clone());
+        if self.is_full(self.nodes.get(ROOT_ID).unwrap()) {
+            self.split_root(ROOT_ID);
+        }
+        let mut current = ROOT_ID;
+        let mut rightmost = true;
+        while let NodeType::Internal(children) = &self.nodes.get(current).unwrap().node_type {
+            let node = self.nodes.get(current).unwrap();
+            let index = node.child_index(&new_kv.key);
+            let child = children[index];
+            if self.is_full(self.nodes.get(child).unwrap()) {
+                match self.split_policy {
+                    SplitPolicy::BStar => self.split_bstar(child, current, 1),
+                    _ => { self.split(child, current, rightmost && index + 1 == children.len()); },
+                }
+            }
+            let node = self.nodes.get(current).unwrap();
+            let index = node.child_index(&new_kv.key);
+            rightmost = rightmost && index == node.keys.len();
+            let child = node.get_child(&new_kv.key);
+            if grows {
+                self.add_count(current, index, 1);
+            }
+            current = child;
+        }
+        let position = self.insert_into_leaf(current, new_kv);
+        if let Some(key) = key {
+            self.refresh_path(&key);
+        }
+        let Some(position) = position else {
+            return false
+        };
+        self.len += 1;
+        self.track_appends(current, position, rightmost);
+        true
+    }
+
+    // Returns the position in the leaf the new key went to, or None when an existing value was overwritten.
+    fn insert_into_leaf(&mut self, leaf: NodeId, new_kv: KeyValue<K, V>) -> Option<usize> {
+        let node = self.nodes.get_mut(leaf).unwrap();
+        match &mut node.node_type {
+            NodeType::Leaf(values) => {
+                match node.keys.binary_search(&new_kv.key) {
+                    Ok(existing_idx) => {
+                        values[existing_idx] = new_kv.value;
+                        None
+                    },
+                    Err(insertion_idx) => {
+                        node.keys.insert(insertion_idx, new_kv.key);
+                        values.insert(insertion_idx, new_kv.value);
+                        Some(insertion_idx)
+                    }
+                }
+            },
+            NodeType::Internal(_) => panic!("insert ended on an internal node"),
+        }
+    }
+
+    // Counts the inserts in a row that went to the end of the rightmost leaf, for `SplitPolicy::Adaptive`.
+    fn track_appends(&mut self, leaf: NodeId, position: usize, rightmost: bool) {
+        if self.split_policy != SplitPolicy::Adaptive {
+            return
+        }
+        if rightmost && position + 1 == self.nodes.get(leaf).unwrap().len() {
+            self.append_run += 1;
+        } else {
+            self.append_run = 0;
+        }
+    }
+
+    // Moves the root's contents into a fresh child and splits that child, so the root keeps its id.
+    fn split_root(&mut self, root_id: NodeId) {
+        let new_root = Node::new(true);
+        let mut old_root = mem::replace(self.nodes.get_mut(root_id).unwrap(), new_root);
+        old_root.is_root = false;
+        let new_node_id = self.nodes.alloc(old_root);
+        match &mut self.nodes.get_mut(root_id).unwrap().node_type {
+            NodeType::Internal(children) => children.push(new_node_id),
+            NodeType::Leaf(_) => panic!("root was just made internal"),
+        }
+        self.split(new_node_id, root_id, true);
+    }
+
+    // Number of nodes at the start of `path` that lie on the right edge of the tree. The root always does.
+    fn rightmost_prefix(&self, path: &TreePath) -> usize {
+        let mut count = 1;
+        for pair in path.steps().windows(2) {
+            match &self.nodes.get(pair[0].0).unwrap().node_type {
+                NodeType::Internal(children) if pair[1].1 + 1 == children.len() => count += 1,
+                _ => break,
+            }
+        }
+        count
+    }
+
+    // How many entries of an overflowing node of `len` entries go to the new left node.
+    fn split_index(&self, len: usize, is_leaf: bool, rightmost: bool) -> usize {
+        let appending = rightmost && match self.split_policy {
+            SplitPolicy::Middle | SplitPolicy::BStar => false,
+            SplitPolicy::Append => true,
+            SplitPolicy::Adaptive => self.append_run >= ADAPTIVE_RUN,
+        };
+        if !appending {
+            return len / 2
+        }
+        // The right node keeps at least one key, or two children so that it still has a separator.
+        let right_min = if is_leaf { 1 } else { 2 };
+        (len * APPEND_FILL_PERCENT / 100).clamp(len / 2, len - right_min)
+    }
+
+    // Only the left part is moved out into the new node; the right part stays where it is.
+    fn split(&mut self, current: NodeId, parent: NodeId, rightmost: bool) -> bool {
+        let node = self.nodes.get(current).unwrap();
+        let middle_index = self.split_index(node.len(), node.is_leaf(), rightmost);
+        let node = self.nodes.get_mut(current).unwrap();
+        let (new_node, divider) = match &mut node.node_type {
+            NodeType::Internal(children) => {
+                let new_node = Node {
+                    keys: node.keys.split_front(middle_index - 1),
+                    node_type: NodeType::Internal(children.split_front(middle_index)),
+                    is_root: false,
+                    prev: None,
+                    next: None,
+                    counts: InlineVec::new(),
+                    summaries: InlineVec::new(),
+                };
+                // The key between the two halves moves up instead of staying in either node.
+                (new_node, node.keys.remove(0))
+            },
+            NodeType::Leaf(values) => {
+                let new_node = Node {
+                    keys: node.keys.split_front(middle_index),
+                    node_type: NodeType::Leaf(values.split_front(middle_index)),
+                    is_root: false,
+                    prev: node.prev,
+                    next: Some(current),
+                    counts: InlineVec::new(),
+                    summaries: InlineVec::new(),
+                };
+                (new_node, node.keys[0].clone())
+            },
+        };
+        let (is_leaf, prev) = (new_node.is_leaf(), new_node.prev);
+        let new_node_id = self.nodes.alloc(new_node);
+        if is_leaf {
+            self.link(prev, Some(new_node_id));
+            self.link(Some(new_node_id), Some(current));
+        }
+
+        let parent_node = self.nodes.get_mut(parent).unwrap();
+        match &mut parent_node.node_type {
+          NodeType::Internal(children) => {
+            let current_index = children.iter().position(|&id| id == current).unwrap();
+            children.insert(current_index, new_node_id);
+            parent_node.keys.insert(current_index, divider);
+          },
+          _ => panic!("No non-internal parent")
+        }
+        self.refresh(new_node_id);
+        self.refresh(current);
+        self.refresh(parent);
+
+       true
+    }
+
+    // Counted mode: adds `delta` to the count of child `index` of `parent`, after an entry below it came or went.
+    fn add_count(&mut self, parent: NodeId, index: usize, delta: isize) {
+        if self.counted {
+            let count = &mut self.nodes.get_mut(parent).unwrap().counts[index];
+            *count = C::from_size(count.size().checked_add_signed(delta).unwrap());
+        }
+    }
+
+    // Computes the counts and aggregates an internal node keeps for its children again. Called on every internal node
+    // whose children changed.
+    fn refresh(&mut self, id: NodeId) {
+        if !self.counted && !Self::AGGREGATING {
+            return
+        }
+        let children = match &self.nodes.get(id).unwrap().node_type {
+            NodeType::Internal(children) => children,
+            NodeType::Leaf(_) => return,
+        };
+        let counts = if self.counted { children.iter().map(|&child| C::from_size(self.subtree_size(child))).collect() } else { InlineVec::new() };
+        let summaries = if Self::AGGREGATING { children.iter().map(|&child| self.subtree_summary(child)).collect() } else { InlineVec::new() };
+        let node = self.nodes.get_mut(id).unwrap();
+        node.counts = counts;
+        node.summaries = summaries;
+    }
+
+    // Computes the aggregate of child `index` of `parent` again, after an entry below it changed.
+    fn refresh_summary(&mut self, parent: NodeId, index: usize) {
+        if !Self::AGGREGATING {
+            return
+        }
+        let summary = match &self.nodes.get(parent).unwrap().node_type {
+            NodeType::Internal(children) => self.subtree_summary(children[index]),
+            NodeType::Leaf(_) => panic!("only internal nodes keep aggregates"),
+        };
+        self.nodes.get_mut(parent).unwrap().summaries[index] = summary;
+    }
+
+    // Refreshes the aggregates along `path` from the leaf up.
+    fn refresh_summaries(&mut self, path: &TreePath) {
+        if !Self::AGGREGATING {
+            return
+        }
+        for pair in path.steps().windows(2).rev() {
+            self.refresh_summary(pair[0].0, pair[1].1);
+        }
+    }
+
+    // Refreshes the aggregates along the path to `key`, for changes made without one at hand.
+    fn refresh_path(&mut self, key: &K) {
+        if Self::AGGREGATING {
+            let mut path = TreePath::new();
+            self.search_path(key, &mut path);
+            self.refresh_summaries(&path);
+        }
+    }
+
+    fn subtree_summary(&self, id: NodeId) -> A::Value {
+        let node = self.nodes.get(id).unwrap();
+        match &node.node_type {
+            NodeType::Internal(_) => Self::fold_summaries(&node.summaries),
+            NodeType::Leaf(values) => Self::fold_entries(&node.keys, values),
+        }
+    }
+
+    fn fold_summaries(summaries: &[A::Value]) -> A::Value {
+        summaries.iter().fold(A::identity(), |acc, summary| A::combine(&acc, summary))
+    }
+
+    fn fold_entries(keys: &[K], values: &[V]) -> A::Value {
+        keys.iter().zip(values).fold(A::identity(), |acc, (key, value)| A::combine(&acc, &A::lift(key, value)))
+    }
+
+    fn subtree_size(&self, id: NodeId) -> usize {
+        let node = self.nodes.get(id).unwrap();
+        match &node.node_type {
+            NodeType::Internal(_) => node.counts.iter().map(|count| count.size()).sum(),
+            NodeType::Leaf(values) => values.len(),
+        }
+    }
+
+    // Points two leaves at each other in the leaf chain. Either side may be missing at the ends of the tree.
+    fn link(&mut self, left: Option<NodeId>, right: Option<NodeId>) {
+        if let Some(left) = left {
+            self.nodes.get_mut(left).unwrap().next = right;
+        }
+        if let Some(right) = right {
+            self.nodes.get_mut(right).unwrap().prev = left;
+        }
+    }
+
+    // Fixes an overflowing node together with its sibling from `get_sibling`. While the two fit in two nodes the entries
+    // are only shifted over; once both are full they are spread over three nodes, each about two thirds full.
+    // `room` is how many free slots every resulting node needs, 1 when a full node is split ahead of an insert.
+    fn split_bstar(&mut self, current: NodeId, parent: NodeId, room: usize) {
+        let mut pair = self.get_sibling(current, parent);
+        pair.sort_by_key(|&(_, index)| index);
+        let capacity = if self.nodes.get(current).unwrap().is_leaf() { self.max_key() } else { self.max_child() };
+        let total: usize = pair.iter().map(|&(id, _)| self.nodes.get(id).unwrap().len()).sum();
+        let new_count = if total <= 2 * (capacity - room) { 2 } else { 3 };
+        self.redistribute(parent, &pair, new_count);
+    }
+
+    pub fn delete(&mut self, key_d: &K) -> bool {
+        self.remove_entry(key_d).is_some()
+    }
+
+    // Deletes `key_d` and hands back its value.
+    fn remove_entry(&mut self, key_d: &K) -> Option<V> {
+        if self.pass == Pass::TopDown {
+            return self.delete_top_down(key_d)
+        }
+        let mut path = TreePath::new();
+        let leaf = self.search_path(key_d, &mut path);
+        let removed = self.delete_from_leaf(leaf, key_d);
+        if removed.is_some() {
+            self.rebalance_path(path);
+        }
+        removed
+    }
+
+    // Fixes the nodes on `path` from the leaf up after an entry was taken out of the leaf at its end.
+    fn rebalance_path(&mut self, mut path: TreePath) {
+        self.len -= 1;
+        while path.len() > 1 {
+            let (node_id, index) = path.pop().unwrap();
+            let (parent, _) = path.last().unwrap();
+            if self.is_short(self.nodes.get(node_id).unwrap(), parent) {
+                self.fix_underflow(node_id, parent);
+            } else {
+                self.add_count(parent, index, -1);
+                self.refresh_summary(parent, index);
+            }
+        }
+
+        self.merge_root(path.pop().unwrap().0);
+    }
+
+
+    // Deletes every key in `range` and returns how many there were. Subtrees that lie completely inside the range are
+    // freed as a whole without looking at their entries; only the nodes along the two edges of the range are trimmed
+    // and rebalanced afterwards.
+    pub fn remove_range<R: RangeBounds<K>>(&mut self, range: R) -> usize {
+        let (start, end) = (range.start_bound(), range.end_bound());
+        let mut removed = 0;
+        // Go down together while both edges lead to the same child.
+        let mut node_id = ROOT_ID;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            let (from, to) = match &node.node_type {
+                NodeType::Leaf(_) => (start_index(&node.keys, start), end_index(&node.keys, end)),
+                NodeType::Internal(_) => (start_child(node, start), end_child(node, end)),
+            };
+            let node = self.nodes.get_mut(node_id).unwrap();
+            match &mut node.node_type {
+                NodeType::Leaf(values) => {
+                    if from < to {
+                        let mut tail_keys = node.keys.split_off(to);
+                        let mut tail_values = values.split_off(to);
+                        node.keys.truncate(from);
+                        values.truncate(from);
+                        node.keys.append(&mut tail_keys);
+                        values.append(&mut tail_values);
+                        removed = to - from;
+                    }
+                    break;
+                },
+                NodeType::Internal(children) => {
+                    if from == to {
+                        node_id = children[from];
+                        continue;
+                    }
+                    if from > to {
+                        return 0
+                    }
+                    // The children between the two edges go as a whole. `keys[to - 1]` stays as the divider between
+                    // the edges: it is above everything left of the range and at most the first key right of it.
+                    let mut tail = children.split_off(to);
+                    let inside = children.split_off(from + 1);
+                    children.append(&mut tail);
+                    let mut tail_keys = node.keys.split_off(to - 1);
+                    node.keys.truncate(from);
+                    node.keys.append(&mut tail_keys);
+                    let (left, right) = (children[from], children[from + 1]);
+                    for child in inside {
+                        removed += self.free_subtree(child);
+                    }
+                    let (left_removed, left_leaf) = self.trim_after(left, start);
+                    let (right_removed, right_leaf) = self.trim_before(right, end);
+                    removed += left_removed + right_removed;
+                    self.link(Some(left_leaf), Some(right_leaf));
+                    break;
+                },
+            }
+        }
+        if removed == 0 {
+            return 0
+        }
+        self.len -= removed;
+
+        // Everything that changed lies on the two edges.
+        self.refresh_edge(start, true);
+        self.refresh_edge(end, false);
+        loop {
+            let left_changed = self.repair_edge(start, true);
+            let right_changed = self.repair_edge(end, false);
+            if !left_changed && !right_changed {
+                break;
+            }
+        }
+        removed
+    }
+
+    // Keeps only the entries `f` returns true for. `f` may also change the values it keeps.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+        self.extract_if(|key, value| !f(key, value)).for_each(drop);
+    }
+
+    // Takes out the entries `pred` returns true for while iterating and hands them out in key order. Entries the
+    // iterator doesn't get to, because it is dropped early, stay in the tree. Between two entries the tree is always
+    // sound to read: counts and aggregates are kept up to date and emptied leaves are taken out right away, so even
+    // an iterator that is leaked instead of dropped leaves a working tree behind, just not a balanced one.
+    pub fn extract_if<F: FnMut(&K, &mut V) -> bool>(&mut self, pred: F) -> ExtractIf<'_, K, V, B, A, C, F> {
+        let leaf = self.first_leaf();
+        ExtractIf { tree: self, position: Some((leaf, 0)), path: TreePath::new(), pred, removed: 0, changed: false }
+    }
+
+    // Takes an emptied leaf at the end of `path` out of the tree, with every ancestor that is left without children.
+    // The counts along the path have to be down to zero for it already.
+    fn unlink_leaf(&mut self, path: &TreePath) {
+        let steps = path.steps();
+        let (leaf, _) = steps[steps.len() - 1];
+        if leaf == ROOT_ID {
+            return
+        }
+        let node = self.nodes.free(leaf);
+        self.link(node.prev, node.next);
+        let mut depth = steps.len() - 1;
+        loop {
+            let ((parent, _), (_, index)) = (steps[depth - 1], steps[depth]);
+            let node = self.nodes.get_mut(parent).unwrap();
+            let NodeType::Internal(children) = &mut node.node_type else { panic!("a leaf's parent is an internal node") };
+            children.remove(index);
+            if !node.keys.is_empty() {
+                node.keys.remove(index.saturating_sub(1));
+            }
+            if self.counted {
+                node.counts.remove(index);
+            }
+            if Self::AGGREGATING {
+                node.summaries.remove(index);
+            }
+            if !children.is_empty() {
+                break
+            }
+            if parent == ROOT_ID {
+                // Nothing is left, the root goes back to being an empty leaf.
+                *node = Node::leaf();
+                node.is_root = true;
+                break
+            }
+            self.nodes.free(parent);
+            depth -= 1;
+        }
+    }
+
+    // Fixes the tree up after entries were taken out of its leaves in place, which can leave any number of them short
+    // or even empty. The counts and aggregates are redone and each short node is topped up from its siblings along
+    // its own path, like the edges of a removed range. Only when a large share of the leaves is short is the tree
+    // bulk loaded again from what is left, which is then cheaper than fixing them one by one.
+    fn restructure(&mut self) {
+        // Unlinking emptied leaves can leave the root with a single child.
+        while self.merge_root(ROOT_ID) {}
+        let (short, leaves) = self.short_nodes();
+        if short.len() * 100 > leaves * RELOAD_SHORT_PERCENT {
+            let tree = mem::replace(self, self.empty_like());
+            self.load_sorted(tree.into_iter());
+            return
+        }
+        if self.counted || Self::AGGREGATING {
+            for id in self.node_ids().into_iter().rev() {
+                self.refresh(id);
+            }
+        }
+        let mut short = short;
+        // Under a two thirds minimum spreading siblings can leave one short that is not next to the path, so the
+        // nodes are checked again until none is left.
+        while !short.is_empty() {
+            for lo in short {
+                self.repair_edge(lo.as_ref().map_or(Bound::Unbounded, Bound::Included), true);
+            }
+            short = self.short_nodes().0;
+        }
+    }
+
+    // The smallest key that can go into each short node, None for the ones on the left edge, and the number of
+    // leaves. An emptied leaf has no key left to find it by, but the separators above it still lead there.
+    fn short_nodes(&self) -> (Vec<Option<K>>, usize) {
+        let mut short = Vec::new();
+        let mut leaves = 0;
+        let mut stack: Vec<(NodeId, NodeId, Option<&K>)> = vec![(ROOT_ID, ROOT_ID, None)];
+        while let Some((id, parent, lo)) = stack.pop() {
+            let node = self.nodes.get(id).unwrap();
+            if id != ROOT_ID && self.is_short(node, parent) {
+                short.push(lo.cloned());
+            }
+            match &node.node_type {
+                NodeType::Internal(children) => {
+                    stack.extend(children.iter().enumerate().map(|(i, &child)| (child, id, if i == 0 { lo } else { Some(&node.keys[i - 1]) })));
+                },
+                NodeType::Leaf(_) => leaves += 1,
+            }
+        }
+        (short, leaves)
+    }
+
+    // Calls `f` on every entry in `range`, letting it change the value in place.
+    pub fn update_range<R: RangeBounds<K>, F: FnMut(&K, &mut V)>(&mut self, range: R, mut f: F) {
+        let (mut position, back) = self.range_positions(&range).unzip();
+        // One key per leaf that was changed, for refreshing the aggregates above it.
+        let mut changed = Vec::new();
+        while let Some((leaf, index)) = position {
+            let node = self.nodes.get_mut(leaf).unwrap();
+            match &mut node.node_type {
+                NodeType::Leaf(values) => f(&node.keys[index], &mut values[index]),
+                NodeType::Internal(_) => panic!("entries only live in leaves"),
+            }
+            if Self::AGGREGATING && (index == 0 || changed.is_empty()) {
+                changed.push(node.keys[index].clone());
+            }
+            position = if position == back { None } else { self.position_at(leaf, index + 1) };
+        }
+        for key in changed {
+            self.refresh_path(&key);
+        }
+    }
+
+    // Frees a subtree and returns how many entries it held.
+    fn free_subtree(&mut self, id: NodeId) -> usize {
+        let mut entries = 0;
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            match self.nodes.free(id).node_type {
+                NodeType::Internal(children) => stack.extend(children),
+                NodeType::Leaf(values) => entries += values.len(),
+            }
+        }
+        entries
+    }
+
+    // Removes everything from `start` on below `node_id`, the left edge of a removed range. Returns how many entries
+    // went and the leaf the edge ends in.
+    fn trim_after(&mut self, mut node_id: NodeId, start: Bound<&K>) -> (usize, NodeId) {
+        let mut removed = 0;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            let index = match &node.node_type {
+                NodeType::Internal(_) => start_child(node, start),
+                NodeType::Leaf(_) => start_index(&node.keys, start),
+            };
+            let node = self.nodes.get_mut(node_id).unwrap();
+            match &mut node.node_type {
+                NodeType::Internal(children) => {
+                    let outside = children.split_off(index + 1);
+                    node.keys.truncate(index);
+                    let next = children[index];
+                    for child in outside {
+                        removed += self.free_subtree(child);
+                    }
+                    node_id = next;
+                },
+                NodeType::Leaf(values) => {
+                    removed += values.len() - index;
+                    node.keys.truncate(index);
+                    values.truncate(index);
+                    return (removed, node_id)
+                },
+            }
+        }
+    }
+
+    // Removes everything before `end` below `node_id`, the right edge of a removed range.
+    fn trim_before(&mut self, mut node_id: NodeId, end: Bound<&K>) -> (usize, NodeId) {
+        let mut removed = 0;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            let index = match &node.node_type {
+                NodeType::Internal(_) => end_child(node, end),
+                NodeType::Leaf(_) => end_index(&node.keys, end),
+            };
+            let node = self.nodes.get_mut(node_id).unwrap();
+            match &mut node.node_type {
+                NodeType::Internal(children) => {
+                    let outside = children.split_front(index);
+                    node.keys.split_front(index);
+                    let next = children[0];
+                    for child in outside {
+                        removed += self.free_subtree(child);
+                    }
+                    node_id = next;
+                },
+                NodeType::Leaf(values) => {
+                    removed += index;
+                    node.keys.split_front(index);
+                    values.split_front(index);
+                    return (removed, node_id)
+                },
+            }
+        }
+    }
+
+    // Refills `path` with the nodes along one edge of a range, following `bound` like `aggregate_range` does.
+    fn edge_path(&self, bound: Bound<&K>, is_start: bool, path: &mut TreePath) {
+        path.clear();
+        let (mut node_id, mut index) = (ROOT_ID, 0);
+        loop {
+            path.push(node_id, index);
+            let node = self.nodes.get(node_id).unwrap();
+            match &node.node_type {
+                NodeType::Leaf(_) => return,
+                NodeType::Internal(children) => {
+                    index = if is_start { start_child(node, bound) } else { end_child(node, bound) };
+                    node_id = children[index];
+                },
+            }
+        }
+    }
+
+    // Recounts the counts and aggregates along one edge of a range from the leaf up, after the nodes on it were cut.
+    fn refresh_edge(&mut self, bound: Bound<&K>, is_start: bool) {
+        if !self.counted && !Self::AGGREGATING {
+            return
+        }
+        let mut path = TreePath::new();
+        self.edge_path(bound, is_start, &mut path);
+        for &(id, _) in path.steps().iter().rev() {
+            self.refresh(id);
+        }
+    }
+
+    // Splits the tree in two at `key`, like `BTreeMap::split_off`: the tree keeps the keys below `key` and the one
+    // returned holds the rest, with the same settings. Only the nodes on the path to `key` are cut, every subtree
+    // beside it goes over whole. The two halves need their own slabs, so the nodes of the smaller half are moved over
+    // to a new one.
+    pub fn split_off(&mut self, key: &K) -> Self {
+        let mut right = self.empty_like();
+        // Cut every node on the path in two. The right halves are chained into a second tree in the same slab.
+        let mut right_root = None;
+        let mut node_id = ROOT_ID;
+        loop {
+            let node = self.nodes.get(node_id).unwrap();
+            let index = match &node.node_type {
+                NodeType::Internal(_) => node.child_index(key),
+                NodeType::Leaf(_) => start_index(&node.keys, Bound::Included(key)),
+            };
+            let node = self.nodes.get_mut(node_id).unwrap();
+            let (right_node, next) = match &mut node.node_type {
+                NodeType::Internal(children) => {
+                    // The right half gets the cut child's right part in front of the children after it, with the key
+                    // between the two as their divider.
+                    let right_node = Node {
+                        keys: node.keys.split_off(index),
+                        node_type: NodeType::Internal(children.split_off(index + 1)),
+                        is_root: false,
+                        prev: None,
+                        next: None,
+                        counts: InlineVec::new(),
+                        summaries: InlineVec::new(),
+                    };
+                    (right_node, Some(children[index]))
+                },
+                NodeType::Leaf(values) => {
+                    let right_node = Node {
+                        keys: node.keys.split_off(index),
+                        node_type: NodeType::Leaf(values.split_off(index)),
+                        is_root: false,
+                        prev: None,
+                        next: node.next.take(),
+                        counts: InlineVec::new(),
+                        summaries: InlineVec::new(),
+                    };
+                    (right_node, None)
+                },
+            };
+            let after = right_node.next;
+            let right_id = self.nodes.alloc(right_node);
+            match right_root {
+                None => right_root = Some((right_id, right_id)),
+                Some((root, parent)) => {
+                    match &mut self.nodes.get_mut(parent).unwrap().node_type {
+                        NodeType::Internal(children) => children.insert(0, right_id),
+                        NodeType::Leaf(_) => panic!("split_off only hangs subtrees off internal nodes"),
+                    }
+                    right_root = Some((root, right_id));
+                },
+            }
+            match next {
+                Some(child) => node_id = child,
+                None => {
+                    self.link(Some(right_id), after);
+                    break;
+                },
+            }
+        }
+        let (other_root, _) = right_root.unwrap();
+
+        // The half that stays in this slab has to sit at the root id, so when that is the right half the two roots
+        // swap slots. The children of the root tell which half is smaller.
+        let keep_right = self.nodes.get(ROOT_ID).unwrap().len() < self.nodes.get(other_root).unwrap().len();
+        if keep_right {
+            let (left_root, moved_root) = self.nodes.get_pair_mut(ROOT_ID, other_root);
+            mem::swap(left_root, moved_root);
+            mem::swap(&mut left_root.is_root, &mut moved_root.is_root);
+        }
+        let (root, moved) = right.adopt(&mut self.nodes, other_root);
+        right.place_root(root);
+        if keep_right {
+            mem::swap(&mut self.nodes, &mut right.nodes);
+            right.len = self.len - moved;
+            self.len = moved;
+        } else {
+            right.len = moved;
+            self.len -= moved;
+        }
+        self.append_run = 0;
+
+        self.refresh_edge(Bound::Unbounded, false);
+        self.repair_edge(Bound::Unbounded, false);
+        right.refresh_edge(Bound::Unbounded, true);
+        right.repair_edge(Bound::Unbounded, true);
+        right
+    }
+
+    // Moves every entry of `other` into this tree and leaves `other` empty. The two trees must not overlap, one has
+    // to hold only keys below the other's. The shorter tree is hung off the edge of the taller one at the level where
+    // its leaves line up, so only the nodes of the shorter one are moved between the slabs.
+    pub fn append(&mut self, other: &mut Self) {
+        let Some((other_first, other_last)) = other.first().zip(other.last()).map(|((a, _), (b, _))| (a.clone(), b.clone())) else {
+            return
+        };
+        let bounds = self.first().zip(self.last()).map(|((a, _), (b, _))| (a.clone(), b.clone()));
+        if let Some((first, last)) = &bounds {
+            assert!(*last < other_first || other_last < *first, "append needs two trees whose keys don't overlap");
+        }
+        // `other` keeps its settings and is left without entries.
+        let mut other = mem::replace(other, other.empty_like());
+        if self.order != other.order || self.min_key() != other.min_key() || self.min_child() != other.min_child() {
+            // The fill limits differ, so the nodes can't be shared and the entries go over one by one.
+            self.extend(other);
+            return
+        }
+        // The nodes keep the counts of the tree they came from.
+        if self.counted != other.counted {
+            other.set_counted(self.counted);
+        }
+        self.len += other.len;
+        let Some((first, last)) = bounds else {
+            mem::swap(&mut self.nodes, &mut other.nodes);
+            // Its right edge may be short if it was built under `SplitPolicy::Append`.
+            self.repair_edge(Bound::Unbounded, false);
+            return
+        };
+
+        // From here on this slab holds the lower tree and `other` the upper one.
+        let (last_low, first_high) = if last < other_first {
+            (last, other_first)
+        } else {
+            mem::swap(&mut self.nodes, &mut other.nodes);
+            (other_last, first)
+        };
+        let (low, high) = (self.height(), other.height());
+        if low >= high {
+            let (sub, _) = self.adopt(&mut other.nodes, ROOT_ID);
+            self.graft(sub, high, first_high.clone(), false);
+        } else {
+            mem::swap(&mut self.nodes, &mut other.nodes);
+            let (sub, _) = self.adopt(&mut other.nodes, ROOT_ID);
+            self.graft(sub, low, first_high.clone(), true);
+        }
+        self.append_run = 0;
+
+        let (left_leaf, _) = self.search(&last_low);
+        let (right_leaf, _) = self.search(&first_high);
+        self.link(Some(left_leaf), Some(right_leaf));
+        // The old roots may be below the minimum, and so may the right edges of both trees when they were built under
+        // `SplitPolicy::Append`: one of them is now inside the tree, the other may not be allowed to be short any more.
+        loop {
+            let mut changed = self.repair_edge(Bound::Included(&last_low), true);
+            changed |= self.repair_edge(Bound::Included(&first_high), true);
+            changed |= self.repair_edge(Bound::Unbounded, false);
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    // Merges two trees into a new one with this tree's settings. Keys in only one of them are taken over as they are;
+    // for a key in both, `resolve` gets the two values, this tree's first, and returns the one to keep.
+    pub fn merge<F: FnMut(&K, V, V) -> V>(self, other: Self, mut resolve: F) -> Self {
+        self.combine(other, |key, a, b| match (a, b) {
+            (Some(a), Some(b)) => Some(resolve(key, a, b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    // Every key of both trees. Where they share a key the value from this tree is kept.
+    pub fn union(self, other: Self) -> Self {
+        self.combine(other, |_, a, b| a.or(b))
+    }
+
+    // The keys both trees have, with the values from this tree.
+    pub fn intersection(self, other: Self) -> Self {
+        self.combine(other, |_, a, b| a.filter(|_| b.is_some()))
+    }
+
+    // The keys of this tree that `other` doesn't have.
+    pub fn difference(self, other: Self) -> Self {
+        self.combine(other, |_, a, b| a.filter(|_| b.is_none()))
+    }
+
+    // Walks the leaves of both trees side by side in key order and bulk loads the entries `pick` keeps into a new tree
+    // with this tree's settings. `pick` gets a key with its value from this tree, from `other`, or from both.
+    fn combine<F: FnMut(&K, Option<V>, Option<V>) -> Option<V>>(self, other: Self, mut pick: F) -> Self {
+        let mut tree = self.empty_like();
+        let mut left = self.into_iter().peekable();
+        let mut right = other.into_iter().peekable();
+        let merged = iter::from_fn(move || loop {
+            let order = match (left.peek(), right.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((a, _)), Some((b, _))) => a.cmp(b),
+            };
+            let (key, a, b) = match order {
+                Ordering::Less => {
+                    let (key, value) = left.next().unwrap();
+                    (key, Some(value), None)
+                },
+                Ordering::Greater => {
+                    let (key, value) = right.next().unwrap();
+                    (key, None, Some(value))
+                },
+                Ordering::Equal => {
+                    let (key, a) = left.next().unwrap();
+                    let (_, b) = right.next().unwrap();
+                    (key, Some(a), Some(b))
+                },
+            };
+            if let Some(value) = pick(&key, a, b) {
+                return Some((key, value))
+            }
+        });
+        tree.load_sorted(merged);
+        tree
+    }
+
+    // A tree without entries and with the same settings.
+    fn empty_like(&self) -> Self {
+        let mut tree = Self::with_order(self.order);
+        tree.rebalance = self.rebalance;
+        tree.split_policy = self.split_policy;
+        tree.pass = self.pass;
+        tree.counted = self.counted;
+        tree
+    }
+
+    // A slab with nothing but an empty root leaf.
+    fn empty_nodes() -> Slab<Node<K, V, B, A::Value, C>> {
+        let mut root = Node::leaf();
+        root.is_root = true;
+        let mut nodes = Slab::new();
+        let root_id = nodes.alloc(root);
+        debug_assert_eq!(root_id, ROOT_ID);
+        nodes
+    }
+
+    // Number of levels, 1 while the root is a leaf.
+    fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node_id = ROOT_ID;
+        while let NodeType::Internal(children) = &self.nodes.get(node_id).unwrap().node_type {
+            node_id = children[0];
+            height += 1;
+        }
+        height
+    }
+
+    // Moves the subtree under `id` out of `from` into this tree's slab and returns its new id with the number of
+    // entries in it. Its leaves are chained to each other again, both ends of the chain are left open.
+    fn adopt(&mut self, from: &mut Slab<Node<K, V, B, A::Value, C>>, id: NodeId) -> (NodeId, usize) {
+        let new_id = self.nodes.alloc(Node::leaf());
+        let mut stack = vec![(id, new_id)];
+        let mut last_leaf = None;
+        let mut entries = 0;
+        while let Some((old_id, new_id)) = stack.pop() {
+            let mut node = from.free(old_id);
+            node.is_root = false;
+            match &mut node.node_type {
+                NodeType::Internal(children) => {
+                    // Pushed in reverse, so the children come off the stack in order and the leaves are met left to right.
+                    let start = stack.len();
+                    for child in children.iter_mut() {
+                        let new_child = self.nodes.alloc(Node::leaf());
+                        stack.push((*child, new_child));
+                        *child = new_child;
+                    }
+                    stack[start..].reverse();
+                },
+                NodeType::Leaf(values) => {
+                    entries += values.len();
+                    node.prev = last_leaf;
+                    node.next = None;
+                    if let Some(last_leaf) = last_leaf {
+                        self.nodes.get_mut(last_leaf).unwrap().next = Some(new_id);
+                    }
+                    last_leaf = Some(new_id);
+                },
+            }
+            *self.nodes.get_mut(new_id).unwrap() = node;
+        }
+        (new_id, entries)
+    }
+
+    // Moves node `id` into the root's slot, replacing what was there.
+    fn place_root(&mut self, id: NodeId) {
+        let mut node = self.nodes.free(id);
+        node.is_root = true;
+        *self.nodes.get_mut(ROOT_ID).unwrap() = node;
+    }
+
+    // Builds the tree bottom up from entries in strictly ascending key order, instead of inserting them one by one.
+    // Leaves are filled up completely as the entries stream in, then every level is grouped under full parents until
+    // a single node is left for the root. The tree has to be empty.
+    fn load_sorted<I: Iterator<Item = (K, V)>>(&mut self, entries: I) {
+        // The nodes of the level being built, each with the smallest key under it, which becomes its divider above.
+        let mut level: Vec<(NodeId, K)> = Vec::new();
+        let mut leaf = None;
+        for (key, value) in entries {
+            let id = match leaf {
+                Some(id) if self.nodes.get(id).unwrap().len() < self.max_key() => id,
+                _ => {
+                    let id = self.nodes.alloc(Node::leaf());
+                    self.link(leaf, Some(id));
+                    level.push((id, key.clone()));
+                    leaf = Some(id);
+                    id
+                },
+            };
+            let node = self.nodes.get_mut(id).unwrap();
+            node.keys.push(key);
+            match &mut node.node_type {
+                NodeType::Leaf(values) => values.push(value),
+                NodeType::Internal(_) => panic!("load_sorted fills leaves with entries"),
+            }
+            self.len += 1;
+        }
+        let Some(last) = leaf else { return };
+        // Only the last leaf can come out short. It then shares the entries of the full ones before it.
+        let last_len = self.nodes.get(last).unwrap().len();
+        if level.len() > 1 && last_len < self.min_key() {
+            let shares = Self::tail_shares(level.len(), self.max_key(), last_len, self.min_key());
+            let tail = level.len() - shares.len();
+            let mut keys = Vec::new();
+            let mut values = Vec::new();
+            for &(id, _) in &level[tail..] {
+                let node = self.nodes.get_mut(id).unwrap();
+                keys.extend(mem::take(&mut node.keys));
+                match &mut node.node_type {
+                    NodeType::Leaf(node_values) => values.extend(mem::take(node_values)),
+                    NodeType::Internal(_) => panic!("entries only live in leaves"),
+                }
+            }
+            let (mut keys, mut values) = (keys.into_iter(), values.into_iter());
+            for ((id, first), share) in level[tail..].iter_mut().zip(shares) {
+                let node = self.nodes.get_mut(*id).unwrap();
+                node.keys.extend(keys.by_ref().take(share));
+                match &mut node.node_type {
+                    NodeType::Leaf(node_values) => node_values.extend(values.by_ref().take(share)),
+                    NodeType::Internal(_) => panic!("entries only live in leaves"),
+                }
+                *first = node.keys[0].clone();
+            }
+        }
+
+        while level.len() > 1 {
+            // Full parents, except that the last ones share their children when the last one would be short.
+            let mut sizes = vec![self.max_child(); level.len().div_ceil(self.max_child())];
+            let count = sizes.len();
+            sizes[count - 1] = level.len() - self.max_child() * (count - 1);
+            if count > 1 && sizes[count - 1] < self.min_child() {
+                let shares = Self::tail_shares(count, self.max_child(), sizes[count - 1], self.min_child());
+                sizes.splice(count - shares.len().., shares);
+            }
+            let mut children = level.into_iter();
+            level = Vec::new();
+            for size in sizes {
+                let mut node = Node::new(false);
+                let mut first = None;
+                for (child, key) in children.by_ref().take(size) {
+                    match first {
+                        None => first = Some(key),
+                        Some(_) => node.keys.push(key),
+                    }
+                    match &mut node.node_type {
+                        NodeType::Internal(node_children) => node_children.push(child),
+                        NodeType::Leaf(_) => panic!("load_sorted builds the levels above the leaves from internal nodes"),
+                    }
+                }
+                let id = self.nodes.alloc(node);
+                self.refresh(id);
+                level.push((id, first.unwrap()));
+            }
+        }
+        self.place_root(level[0].0);
+    }
+
+    // Shares for the last nodes of a bulk loaded level when the last of `count` nodes would only get `last` of its
+    // `capacity` entries: the last two split their entries evenly, or the last three when two would stay below `min`.
+    fn tail_shares(count: usize, capacity: usize, last: usize, min: usize) -> Vec<usize> {
+        let nodes = if (capacity + last) / 2 >= min { 2 } else { 3 }.min(count);
+        let total = capacity * (nodes - 1) + last;
+        (0..nodes).map(|i| total / nodes + usize::from(i < total % nodes)).collect()
+    }
+
+    // Hangs the subtree `sub`, which is `height` levels high, off the left or the right edge of the tree, on the level
+    // where its leaves line up with the tree's. `divider` is the smallest key of the upper tree. Nodes that
+    // overflow on the way back up are split like after an insert.
+    fn graft(&mut self, sub: NodeId, height: usize, divider: K, front: bool) {
+        let tree_height = self.height();
+        if tree_height == height {
+            let mut old_root = mem::replace(self.nodes.get_mut(ROOT_ID).unwrap(), Node::new(true));
+            old_root.is_root = false;
+            let old_id = self.nodes.alloc(old_root);
+            let root = self.nodes.get_mut(ROOT_ID).unwrap();
+            root.keys.push(divider);
+            match &mut root.node_type {
+                NodeType::Internal(children) if front => children.extend([sub, old_id]),
+                NodeType::Internal(children) => children.extend([old_id, sub]),
+                NodeType::Leaf(_) => panic!("root was just made internal"),
+            }
+            self.refresh(ROOT_ID);
+            return
+        }
+        let mut path = TreePath::new();
+        let (mut node_id, mut index) = (ROOT_ID, 0);
+        for _ in height + 1..tree_height {
+            path.push(node_id, index);
+            match &self.nodes.get(node_id).unwrap().node_type {
+                NodeType::Internal(children) => {
+                    index = if front { 0 } else { children.len() - 1 };
+                    node_id = children[index];
+                },
+                NodeType::Leaf(_) => panic!("graft only descends through internal nodes above the subtree's height"),
+            }
+        }
+        path.push(node_id, index);
+        let node = self.nodes.get_mut(node_id).unwrap();
+        match &mut node.node_type {
+            NodeType::Internal(children) if front => {
+                node.keys.insert(0, divider);
+                children.insert(0, sub);
+            },
+            NodeType::Internal(children) => {
+                node.keys.push(divider);
+                children.push(sub);
+            },
+            NodeType::Leaf(_) => panic!("graft hangs the subtree off an internal node"),
+        }
+        while path.len() > 1 {
+            let (node_id, _) = path.pop().unwrap();
+            let (parent, _) = path.last().unwrap();
+            if self.is_overflow(self.nodes.get(node_id).unwrap()) {
+                self.split(node_id, parent, false);
+            } else {
+                self.refresh(node_id);
+            }
+        }
+        if self.is_overflow(self.nodes.get(ROOT_ID).unwrap()) {
+            self.split_root(ROOT_ID);
+        } else {
+            self.refresh(ROOT_ID);
+        }
+    }
+
+    // Fixes one underflowing node along an edge of a removed range and returns whether there was one. The topmost
+    // one is fixed first, so its parent is always sound. A trimmed node can be far below the minimum, or even an
+    // empty leaf, so this runs until the edge is clean.
+    fn repair_edge(&mut self, bound: Bound<&K>, is_start: bool) -> bool {
+        let mut changed = false;
+        'descent: loop {
+            while self.merge_root(ROOT_ID) {
+                changed = true;
+            }
+            let mut parent = ROOT_ID;
+            while let NodeType::Internal(children) = &self.nodes.get(parent).unwrap().node_type {
+                let node = self.nodes.get(parent).unwrap();
+                let index = if is_start { start_child(node, bound) } else { end_child(node, bound) };
+                // When both edges end up next to each other, spreading them and a third sibling may not bring all of
+                // them up to a two thirds minimum. The one left short is then a neighbour of the edge.
+                let around = index.saturating_sub(1)..(index + 2).min(children.len());
+                if let Some(short) = around.map(|i| children[i]).find(|&id| self.is_short(self.nodes.get(id).unwrap(), parent)) {
+                    self.fix_underflow(short, parent);
+                    changed = true;
+                    continue 'descent;
+                }
+                parent = children[index];
+            }
+            return changed
+        }
+    }
+
+    // Tops up an underflowing node from its siblings. A two thirds minimum can't be kept by trading with a single
+    // sibling, so it always spreads three siblings over two or three nodes.
+    fn fix_underflow(&mut self, current: NodeId, parent: NodeId) {
+        match self.rebalance {
+            Rebalance::TwoNode if !self.two_thirds_min() => self.distribute_mini(current, parent),
+            _ => self.balance_siblings(current, parent),
+        }
+    }
+
+    // Returns [(current, index in parent), (sibling, index in parent)]. The sibling is the left one unless `current` is the first child.
+    fn get_sibling(&self , current: NodeId, parent: NodeId) -> [(NodeId, usize); 2]{
+       let parent = self.nodes.get(parent).unwrap();
+       match &parent.node_type {
+        NodeType::Internal(children) => {
+            let index_current = children.iter().position(|&id| id == current).unwrap_or(0);
+            if index_current == 0 {
+                [(current, 0), (children[1], 1)]
+            } else {
+                [(current, index_current), (children[index_current - 1], index_current - 1)]
+            }
+        },
+        _ => panic!("_+_")
+       }
+    }
+
+    // Single pass delete. A node with only the minimum number of entries is topped up from its sibling before the descent
+    // enters it, so its parent can always give up a child and the leaf can always give up a key.
+    fn delete_top_down(&mut self, key_d: &K) -> Option<V> {
+        let shrinks = self.counted && self.get(key_d).is_some();
+        let mut current = ROOT_ID;
+        while let NodeType::Internal(children) = &self.nodes.get(current).unwrap().node_type {
+            let index = self.nodes.get(current).unwrap().child_index(key_d);
+            let child = children[index];
+            if self.is_minimal(self.nodes.get(child).unwrap()) {
+                self.distribute_mini(child, current);
+                // A merge below the root can leave it with a single child, which then takes the child's place.
+                if current == ROOT_ID {
+                    self.merge_root(ROOT_ID);
+                }
+                continue;
+            }
+            if shrinks {
+                self.add_count(current, index, -1);
+            }
+            current = child;
+        }
+        let removed = self.delete_from_leaf(current, key_d);
+        if removed.is_some() {
+            self.len -= 1;
+            self.refresh_path(key_d);
+        }
+        removed
+    }
+
+    fn delete_from_leaf(&mut self, leaf: NodeId, key_d: &K) -> Option<V> {
+        let node = self.nodes.get_mut(leaf).unwrap();
+        match &mut node.node_type {
+            NodeType::Leaf(values) => {
+                match node.keys.binary_search(key_d) {
+                    Ok(i) => {
+                        node.keys.remove(i);
+                        Some(values.remove(i))
+                    },
+                    Err(_) => None,
+                }
+            },
+            NodeType::Internal(_) => panic!("delete ended on an internal node"),
+        }
+    }
+
+    // Either merges the underflowing node with its sibling into the right-hand node, or moves a single entry across.
+    // Only the entries that change owner are moved; nothing is copied into a scratch buffer.
+    fn distribute_mini(&mut self, current: NodeId, parent: NodeId) {
+       let siblings = self.get_sibling(current, parent);
+       let ((left, left_index), (right, _)) = if siblings[0].1 < siblings[1].1 {
+           (siblings[0], siblings[1])
+       } else {
+           (siblings[1], siblings[0])
+       };
+       let left_node = self.nodes.get(left).unwrap();
+       let capacity = if left_node.is_leaf() { self.max_key() } else { self.max_child() };
+       let total = left_node.len() + self.nodes.get(right).unwrap().len();
+
+       if total <= capacity {
+           let parent_node = self.nodes.get_mut(parent).unwrap();
+           let divider = parent_node.keys.remove(left_index);
+           match &mut parent_node.node_type {
+               NodeType::Internal(children) => {
+                   children.remove(left_index);
+               },
+               _ => panic!("___")
+           }
+           let mut left_node = self.nodes.free(left);
+           if left_node.is_leaf() {
+               self.link(left_node.prev, Some(right));
+           }
+           let right_node = self.nodes.get_mut(right).unwrap();
+           match (&mut left_node.node_type, &mut right_node.node_type) {
+               (NodeType::Internal(left_children), NodeType::Internal(children)) => {
+                   // The parent's divider comes down between the two key ranges.
+                   left_node.keys.push(divider);
+                   right_node.keys.prepend(&mut left_node.keys);
+                   children.prepend(left_children);
+               },
+               (NodeType::Leaf(left_values), NodeType::Leaf(values)) => {
+                   right_node.keys.prepend(&mut left_node.keys);
+                   values.prepend(left_values);
+               },
+               _ => panic!("___")
+           }
+       } else if left == current {
+           let (left_node, right_node) = self.nodes.get_pair_mut(left, right);
+           match (&mut left_node.node_type, &mut right_node.node_type) {
+               (NodeType::Internal(left_children), NodeType::Internal(children)) => {
+                   left_children.push(children.remove(0));
+                   let new_bound = right_node.keys.remove(0);
+                   let divider = self.replace_divider(parent, left_index, new_bound);
+                   self.nodes.get_mut(left).unwrap().keys.push(divider);
+               },
+               (NodeType::Leaf(left_values), NodeType::Leaf(values)) => {
+                   left_node.keys.push(right_node.keys.remove(0));
+                   left_values.push(values.remove(0));
+                   let new_bound = right_node.keys[0].clone();
+                   self.replace_divider(parent, left_index, new_bound);
+               },
+               _ => panic!("___")
+           }
+       } else {
+           let (left_node, right_node) = self.nodes.get_pair_mut(left, right);
+           match (&mut left_node.node_type, &mut right_node.node_type) {
+               (NodeType::Internal(left_children), NodeType::Internal(children)) => {
+                   children.insert(0, left_children.pop().unwrap());
+                   let new_bound = left_node.keys.pop().unwrap();
+                   let divider = self.replace_divider(parent, left_index, new_bound);
+                   self.nodes.get_mut(right).unwrap().keys.insert(0, divider);
+               },
+               (NodeType::Leaf(left_values), NodeType::Leaf(values)) => {
+                   right_node.keys.insert(0, left_node.keys.pop().unwrap());
+                   values.insert(0, left_values.pop().unwrap());
+                   let new_bound = right_node.keys[0].clone();
+                   self.replace_divider(parent, left_index, new_bound);
+               },
+               _ => panic!("___")
+           }
+       }
+       if total > capacity {
+           self.refresh(left);
+       }
+       self.refresh(right);
+       self.refresh(parent);
+     }
+
+     // Swaps the separator at `index` in `parent` for `key` and hands back the old one.
+     fn replace_divider(&mut self, parent: NodeId, index: usize, key: K) -> K {
+        mem::replace(&mut self.nodes.get_mut(parent).unwrap().keys[index], key)
+     }
+
+     // Collapses a root with a single child into that child, which may also turn the root back into a leaf.
+     fn merge_root(&mut self, root_id: NodeId) -> bool{
+        let root = self.nodes.get(root_id).unwrap();
+        let child_id = match &root.node_type {
+         NodeType::Internal(children) => {
+            if children.len() > 1 {
+                return false
+            }
+            children[0]
+         },
+         NodeType::Leaf(_) => return false,
+        };
+
+        let mut child = self.nodes.free(child_id);
+        child.is_root = true;
+        *self.nodes.get_mut(root_id).unwrap() = child;
+        true
+     }
+
+    // Spreads the entries of up to three neighbouring siblings over as few nodes as they fit in, like SQLite's balance_siblings
+    // (https://sqlite.org/btreemodule.html#balance_siblings). Siblings that end up empty are freed.
+    fn balance_siblings(&mut self, current: NodeId, parent: NodeId) {
+        let siblings = self.siblings(current, parent);
+        let node = self.nodes.get(current).unwrap();
+        let capacity = if node.is_leaf() { self.max_key() } else { self.max_child() };
+        let total: usize = siblings.iter().map(|&(id, _)| self.nodes.get(id).unwrap().len()).sum();
+        self.redistribute(parent, &siblings, total.div_ceil(capacity).max(1));
+    }
+
+    // Takes every entry out of `siblings`, consecutive children of `parent` ordered by index, and deals them out evenly
+    // over `new_count` nodes. Siblings that are left over are freed, missing nodes are allocated right after them.
+    fn redistribute(&mut self, parent: NodeId, siblings: &[(NodeId, usize)], new_count: usize) {
+        let first = siblings[0].1;
+        let count = siblings.len();
+        let is_leaf = self.nodes.get(siblings[0].0).unwrap().is_leaf();
+        // The leaves just outside the group, which the refilled leaves are chained between.
+        let before = self.nodes.get(siblings[0].0).unwrap().prev;
+        let after = self.nodes.get(siblings[count - 1].0).unwrap().next;
+
+        let parent_node = self.nodes.get_mut(parent).unwrap();
+        let dividers: Vec<K> = (1..count).map(|_| parent_node.keys.remove(first)).collect();
+        let mut dividers = dividers.into_iter();
+
+        // Pull every entry out of the siblings. Between internal nodes the parent's divider comes down with them.
+        let mut keys = Vec::new();
+        let mut children = Vec::new();
+        let mut values = Vec::new();
+        for &(id, _) in siblings {
+            let node = self.nodes.get_mut(id).unwrap();
+            keys.extend(mem::take(&mut node.keys));
+            match &mut node.node_type {
+                NodeType::Internal(node_children) => {
+                    children.extend(mem::take(node_children));
+                    if let Some(divider) = dividers.next() {
+                        keys.push(divider);
+                    }
+                },
+                NodeType::Leaf(node_values) => values.extend(mem::take(node_values)),
+            }
+        }
+
+        for &(id, _) in siblings.iter().skip(new_count) {
+            self.nodes.free(id);
+        }
+        let ids: Vec<NodeId> = (0..new_count).map(|i| match siblings.get(i) {
+            Some(&(id, _)) => id,
+            None => self.nodes.alloc(if is_leaf { Node::leaf() } else { Node::new(false) }),
+        }).collect();
+
+        let total = if is_leaf { values.len() } else { children.len() };
+        let mut keys = keys.into_iter();
+        let mut children = children.into_iter();
+        let mut values = values.into_iter();
+        let mut new_dividers = Vec::new();
+        for (i, &id) in ids.iter().enumerate() {
+            let share = total / new_count + usize::from(i < total % new_count);
+            let node = self.nodes.get_mut(id).unwrap();
+            match &mut node.node_type {
+                NodeType::Internal(node_children) => {
+                    node_children.extend(children.by_ref().take(share));
+                    node.keys.extend(keys.by_ref().take(share - 1));
+                    if i + 1 < new_count {
+                        new_dividers.push(keys.next().unwrap());
+                    }
+                },
+                NodeType::Leaf(node_values) => {
+                    node.keys.extend(keys.by_ref().take(share));
+                    node_values.extend(values.by_ref().take(share));
+                    if i > 0 {
+                        new_dividers.push(node.keys[0].clone());
+                    }
+                },
+            }
+        }
+
+        if is_leaf {
+            let mut prev = before;
+            for &id in &ids {
+                self.link(prev, Some(id));
+                prev = Some(id);
+            }
+            self.link(prev, after);
+        }
+
+        // The kept siblings keep their slots in the parent, so only the tail changes: it is dropped or extended.
+        let parent_node = self.nodes.get_mut(parent).unwrap();
+        match &mut parent_node.node_type {
+            NodeType::Internal(parent_children) => {
+                for _ in new_count..count {
+                    parent_children.remove(first + new_count);
+                }
+                for (i, &id) in ids.iter().enumerate().skip(count) {
+                    parent_children.insert(first + i, id);
+                }
+            },
+            _ => panic!("No non-internal parent")
+        }
+        for (i, divider) in new_dividers.into_iter().enumerate() {
+            parent_node.keys.insert(first + i, divider);
+        }
+        for &id in &ids {
+            self.refresh(id);
+        }
+        self.refresh(parent);
+    }
+
+    // Returns `current` and up to two of its neighbours as (id, index in parent), ordered by index.
+    // A node at either end of the parent takes both neighbours from the one side it has.
+    fn siblings(&self, current: NodeId, parent: NodeId) -> Vec<(NodeId, usize)> {
+        match &self.nodes.get(parent).unwrap().node_type {
+            NodeType::Internal(children) => {
+                let index = children.iter().position(|&id| id == current).unwrap();
+                let window = children.len().min(3);
+                let start = index.saturating_sub(1).min(children.len() - window);
+                (start..start + window).map(|i| (children[i], i)).collect()
+            },
+            _ => panic!("siblings fn")
+        }
+    }
+
+}
+
+// Where the entries after a range's start begin among `keys`, and where the ones before its end stop.
+fn start_index<K: Ord>(keys: &[K], start: Bound<&K>) -> usize {
+    match start {
+        Bound::Included(key) => keys.partition_point(|k| k < key),
+        Bound::Excluded(key) => keys.partition_point(|k| k <= key),
+        Bound::Unbounded => 0,
+    }
+}
+
+fn end_index<K: Ord>(keys: &[K], end: Bound<&K>) -> usize {
+    match end {
+        Bound::Included(key) => keys.partition_point(|k| k <= key),
+        Bound::Excluded(key) => keys.partition_point(|k| k < key),
+        Bound::Unbounded => keys.len(),
+    }
+}
+
+// The children of an internal node that a range's start and end fall into.
+fn start_child<K: Ord, V, const B: usize, S, C>(node: &Node<K, V, B, S, C>, start: Bound<&K>) -> usize {
+    match start {
+        Bound::Included(key) | Bound::Excluded(key) => node.child_index(key),
+        Bound::Unbounded => 0,
+    }
+}
+
+fn end_child<K: Ord, V, const B: usize, S, C>(node: &Node<K, V, B, S, C>, end: Bound<&K>) -> usize {
+    match end {
+        Bound::Included(key) | Bound::Excluded(key) => node.child_index(key),
+        Bound::Unbounded => node.keys.len(),
+    }
+}
+
+// A position on an entry of the tree that moves in both directions along the leaf chain. It holds the tree mutably,
+// so the current value can be changed in place and the current entry can be deleted without losing the position.
+// After moving past either end the cursor points at nothing until it is placed again with one of the seeks.
+pub struct Cursor<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    tree: &'a mut BPlusTree<K, V, B, A, C>,
+    // Leaf and index of the current entry.
+    position: Option<(NodeId, usize)>,
+    // Set by `value_mut`. The aggregates above the current entry are refreshed once the cursor leaves it.
+    changed: bool,
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Cursor<'a, K, V, B, A, C> {
+    fn settle(&mut self) {
+        if mem::take(&mut self.changed) {
+            if let Some(key) = self.key().cloned() {
+                self.tree.refresh_path(&key);
+            }
+        }
+    }
+
+    // Moves to the first entry whose key is >= `key`.
+    pub fn seek(&mut self, key: &K) {
+        self.settle();
+        self.position = self.tree.lower_bound(key, false);
+    }
+
+    pub fn seek_first(&mut self) {
+        self.settle();
+        self.position = self.tree.position_at(self.tree.first_leaf(), 0);
+    }
+
+    pub fn seek_last(&mut self) {
+        self.settle();
+        self.position = self.tree.last_position();
+    }
+
+    // Steps to the next entry. Returns false once it moves past the last one.
+    // Not an `Iterator`: a cursor also goes backwards and can be placed again after running off an end.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        self.settle();
+        if let Some((leaf, index)) = self.position {
+            self.position = self.tree.position_at(leaf, index + 1);
+        }
+        self.position.is_some()
+    }
+
+    // Steps to the previous entry. Returns false once it moves past the first one.
+    pub fn prev(&mut self) -> bool {
+        self.settle();
+        if let Some((leaf, index)) = self.position {
+            self.position = match index {
+                0 => self.tree.position_before(leaf),
+                _ => Some((leaf, index - 1)),
+            };
+        }
+        self.position.is_some()
+    }
+
+    pub fn current(&self) -> Option<(&K, &V)> {
+        self.position.map(|position| self.tree.entry_at(position))
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.current().map(|(key, _)| key)
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        self.current().map(|(_, value)| value)
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        let (leaf, index) = self.position?;
+        self.changed = true;
+        match &mut self.tree.nodes.get_mut(leaf).unwrap().node_type {
+            NodeType::Leaf(values) => Some(&mut values[index]),
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        }
+    }
+
+    // Deletes the current entry and moves on to the one after it. The delete may merge or rebalance leaves, so the
+    // position is found again by key instead of being patched up.
+    pub fn delete(&mut self) -> Option<KeyValue<K, V>> {
+        self.changed = false;
+        let key = self.key()?.clone();
+        let value = self.tree.remove_entry(&key)?;
+        self.seek(&key);
+        Some(KeyValue { key, value })
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Drop for Cursor<'a, K, V, B, A, C> {
+    fn drop(&mut self) {
+        self.settle();
+    }
+}
+
+// A key's place in the tree, found by `entry` with a single descent. The path to the leaf is kept, so an insert only
+// goes back up it, splitting nodes when the leaf overflows, and a removal rebalances along it; in top-down mode too.
+// With an aggregate every change goes through the entry and brings the aggregates along the path up to date, so the
+// methods that hand out a `&mut V` (`or_insert` and its family, `get_mut`, `into_mut`) are only there for a tree
+// without one. The others use `or_insert_entry`, `insert_entry`, `and_modify` and `modify` instead.
+pub enum Entry<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    Occupied(OccupiedEntry<'a, K, V, B, A, C>),
+    Vacant(VacantEntry<'a, K, V, B, A, C>),
+}
+
+pub struct OccupiedEntry<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    tree: &'a mut BPlusTree<K, V, B, A, C>,
+    // From the root down to the leaf that holds the entry, at `index`.
+    path: TreePath,
+    index: usize,
+}
+
+pub struct VacantEntry<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    tree: &'a mut BPlusTree<K, V, B, A, C>,
+    key: K,
+    // From the root down to the leaf the key belongs in, at `index`.
+    path: TreePath,
+    index: usize,
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Entry<'a, K, V, B, A, C> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    // Like `or_insert`, but returns the entry, whose changes keep the aggregates up to date.
+    pub fn or_insert_entry(self, default: V) -> OccupiedEntry<'a, K, V, B, A, C> {
+        match self {
+            Entry::Occupied(entry) => entry,
+            Entry::Vacant(entry) => entry.insert_entry(default),
+        }
+    }
+
+    // Calls `f` on the value if there is one.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                entry.modify(f);
+                Entry::Occupied(entry)
+            },
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V, Value = ()>, C: Count> Entry<'a, K, V, B, A, C> {
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_entry(default).into_mut()
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V where V: Default {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> OccupiedEntry<'a, K, V, B, A, C> {
+    fn leaf(&self) -> &Node<K, V, B, A::Value, C> {
+        self.tree.nodes.get(self.path.last().unwrap().0).unwrap()
+    }
+
+    fn value_mut(&mut self) -> &mut V {
+        let leaf = self.tree.nodes.get_mut(self.path.last().unwrap().0).unwrap();
+        match &mut leaf.node_type {
+            NodeType::Leaf(values) => &mut values[self.index],
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.leaf().keys[self.index]
+    }
+
+    pub fn get(&self) -> &V {
+        match &self.leaf().node_type {
+            NodeType::Leaf(values) => &values[self.index],
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        }
+    }
+
+    // Calls `f` on the value and brings the aggregates up to date.
+    pub fn modify<F: FnOnce(&mut V)>(&mut self, f: F) {
+        f(self.value_mut());
+        self.tree.refresh_summaries(&self.path);
+    }
+
+    // Replaces the value and returns the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        let old = mem::replace(self.value_mut(), value);
+        self.tree.refresh_summaries(&self.path);
+        old
+    }
+
+    // Takes the entry out of the tree and returns its value.
+    pub fn remove(self) -> V {
+        let OccupiedEntry { tree, path, index } = self;
+        let leaf = tree.nodes.get_mut(path.last().unwrap().0).unwrap();
+        let value = match &mut leaf.node_type {
+            NodeType::Leaf(values) => values.remove(index),
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        };
+        leaf.keys.remove(index);
+        tree.rebalance_path(path);
+        value
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V, Value = ()>, C: Count> OccupiedEntry<'a, K, V, B, A, C> {
+    pub fn get_mut(&mut self) -> &mut V {
+        self.value_mut()
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        let OccupiedEntry { tree, path, index } = self;
+        match &mut tree.nodes.get_mut(path.last().unwrap().0).unwrap().node_type {
+            NodeType::Leaf(values) => &mut values[index],
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        }
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> VacantEntry<'a, K, V, B, A, C> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    // Puts the key in with `value` and returns the entry for it.
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, K, V, B, A, C> {
+        let VacantEntry { tree, key, mut path, index } = self;
+        let (leaf, _) = path.last().unwrap();
+        // Only a leaf that overflows moves entries around, and then the key has to be looked up again afterwards.
+        let moved = tree.is_full(tree.nodes.get(leaf).unwrap()).then(|| key.clone());
+        let node = tree.nodes.get_mut(leaf).unwrap();
+        node.keys.insert(index, key);
+        match &mut node.node_type {
+            NodeType::Leaf(values) => values.insert(index, value),
+            NodeType::Internal(_) => panic!("entries only live in leaves"),
+        }
+        let index = match moved {
+            Some(key) => {
+                tree.split_path(path, index);
+                path = TreePath::new();
+                let leaf = tree.search_path(&key, &mut path);
+                tree.nodes.get(leaf).unwrap().keys.binary_search(&key).unwrap()
+            },
+            None => {
+                tree.split_path(path.clone(), index);
+                index
+            },
+        };
+        OccupiedEntry { tree, path, index }
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V, Value = ()>, C: Count> VacantEntry<'a, K, V, B, A, C> {
+    // Puts the key in with `value` and returns a reference to the value.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.insert_entry(value).into_mut()
+    }
+}
+
+// Iterator over `(&key, &value)` pairs returned by `iter` and `range`. The front and the back follow the leaf chain
+// towards each other, so reverse scans never go back to the root.
+pub struct Iter<'a, K, V, const B: usize, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    tree: &'a BPlusTree<K, V, B, A, C>,
+    // The next entries to hand out from either end, both None once they have met.
+    front: Option<(NodeId, usize)>,
+    back: Option<(NodeId, usize)>,
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Iterator for Iter<'a, K, V, B, A, C> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let front = self.front?;
+        if self.front == self.back {
+            self.front = None;
+            self.back = None;
+        } else {
+            self.front = self.tree.position_at(front.0, front.1 + 1);
+        }
+        Some(self.tree.entry_at(front))
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> DoubleEndedIterator for Iter<'a, K, V, B, A, C> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let back = self.back?;
+        if self.front == self.back {
+            self.front = None;
+            self.back = None;
+        } else {
+            self.back = match back.1 {
+                0 => self.tree.position_before(back.0),
+                index => Some((back.0, index - 1)),
+            };
+        }
+        Some(self.tree.entry_at(back))
+    }
+}
+
+// Iterator returned by `extract_if`. Entries are taken out of their leaves as it goes, keeping the counts and
+// aggregates along the way up to date, and the tree is rebalanced once, when it is dropped.
+pub struct ExtractIf<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count, F: FnMut(&K, &mut V) -> bool> {
+    tree: &'a mut BPlusTree<K, V, B, A, C>,
+    // Leaf and index of the next entry to look at.
+    position: Option<(NodeId, usize)>,
+    // From the root down to the leaf in `position`, found again whenever the iterator moves to another leaf.
+    path: TreePath,
+    pred: F,
+    removed: usize,
+    // Whether `pred` was handed values of the current leaf that stayed, which an aggregate has to see.
+    changed: bool,
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count, F: FnMut(&K, &mut V) -> bool> Iterator for ExtractIf<'a, K, V, B, A, C, F> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((leaf, index)) = self.position {
+            let node = self.tree.nodes.get(leaf).unwrap();
+            if index == node.keys.len() {
+                self.position = node.next.map(|next| (next, 0));
+                if mem::take(&mut self.changed) {
+                    self.tree.refresh_summaries(&self.path);
+                }
+                continue;
+            }
+            if self.path.last().map(|(id, _)| id) != Some(leaf) {
+                let first = node.keys[0].clone();
+                self.tree.search_path(&first, &mut self.path);
+            }
+            let node = self.tree.nodes.get_mut(leaf).unwrap();
+            let NodeType::Leaf(values) = &mut node.node_type else { panic!("entries only live in leaves") };
+            if !(self.pred)(&node.keys[index], &mut values[index]) {
+                self.changed |= BPlusTree::<K, V, B, A, C>::AGGREGATING;
+                self.position = Some((leaf, index + 1));
+                continue;
+            }
+            let entry = (node.keys.remove(index), values.remove(index));
+            let (emptied, next) = (values.is_empty(), node.next);
+            self.removed += 1;
+            self.tree.len -= 1;
+            for pair in self.path.steps().windows(2) {
+                self.tree.add_count(pair[0].0, pair[1].1, -1);
+            }
+            self.tree.refresh_summaries(&self.path);
+            self.changed = false;
+            if emptied {
+                self.tree.unlink_leaf(&self.path);
+                self.path.clear();
+                self.position = next.map(|next| (next, 0));
+            }
+            return Some(entry)
+        }
+        None
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count, F: FnMut(&K, &mut V) -> bool> Drop for ExtractIf<'a, K, V, B, A, C, F> {
+    fn drop(&mut self) {
+        // The predicate may also have changed values that stayed, which an aggregate has to see.
+        if self.removed > 0 || BPlusTree::<K, V, B, A, C>::AGGREGATING {
+            self.tree.restructure();
+        }
+    }
+}
+
+// Owns the nodes of a tree and hands out its entries in key order, freeing each leaf once it is used up.
+pub struct IntoIter<K, V, const B: usize, A: Aggregate<K, V> = NoAggregate, C: Count = ()> {
+    nodes: Slab<Node<K, V, B, A::Value, C>>,
+    // The next leaf to take apart.
+    leaf: Option<NodeId>,
+    // What is left of the current leaf.
+    entries: Option<Zip<inline_vec::IntoIter<K, B>, inline_vec::IntoIter<V, B>>>,
+    len: usize,
+}
+
+impl<K, V, const B: usize, A: Aggregate<K, V>, C: Count> Iterator for IntoIter<K, V, B, A, C> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.as_mut().and_then(Iterator::next) {
+                self.len -= 1;
+                return Some(entry)
+            }
+            let node = self.nodes.free(self.leaf?);
+            self.leaf = node.next;
+            match node.node_type {
+                NodeType::Leaf(values) => self.entries = Some(node.keys.into_iter().zip(values)),
+                NodeType::Internal(_) => panic!("the leaf chain ran into an internal node"),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, const B: usize, A: Aggregate<K, V>, C: Count> ExactSizeIterator for IntoIter<K, V, B, A, C> {}
+
+impl<K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> IntoIterator for BPlusTree<K, V, B, A, C> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, B, A, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { leaf: Some(self.first_leaf()), len: self.len, nodes: self.nodes, entries: None }
+    }
+}
+
+impl<'a, K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> IntoIterator for &'a BPlusTree<K, V, B, A, C> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, B, A, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Sorts the entries and bulk loads them into a tree of the default order. A key that comes more than once keeps its
+// last value, like in a `BTreeMap`.
+impl<K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> FromIterator<(K, V)> for BPlusTree<K, V, B, A, C> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        // A stable sort, so the entries of a key stay in the order they came in.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut entries = entries.into_iter().peekable();
+        let mut tree = Self::default();
+        tree.load_sorted(iter::from_fn(|| loop {
+            let entry = entries.next()?;
+            if entries.peek().is_none_or(|next| next.0 != entry.0) {
+                return Some(entry)
+            }
+        }));
+        tree
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Extend<(K, V)> for BPlusTree<K, V, B, A, C> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(KeyValue { key, value });
+        }
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize, A: Aggregate<K, V>, C: Count> Index<&K> for BPlusTree<K, V, B, A, C> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+// Two trees are equal when they hold the same entries, however their nodes are laid out.
+impl<K: Ord + Clone, V: PartialEq, const B: usize, A: Aggregate<K, V>, C: Count> PartialEq for BPlusTree<K, V, B, A, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Ord + Clone, V: Eq, const B: usize, A: Aggregate<K, V>, C: Count> Eq for BPlusTree<K, V, B, A, C> {}
+
+// Shows the entries like a map; `print_tree` shows the nodes.
+impl<K: Ord + Clone + Debug, V: Debug, const B: usize, A: Aggregate<K, V>, C: Count> Debug for BPlusTree<K, V, B, A, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests;
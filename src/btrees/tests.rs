@@ -0,0 +1,473 @@
+use super::*;
+use crate::aggregate::Sum;
+use std::collections::BTreeMap;
+
+// Small xorshift generator, so the tests need no dependencies and every run sees the same operations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u32) -> u32 {
+        (self.next() % n as u64) as u32
+    }
+}
+
+type Tree = BPlusTree<u32, u64, 12, Sum, usize>;
+
+// Every combination of the tree settings, picked by `seed`.
+fn configured(order: usize, seed: u64) -> Tree {
+    let mut tree = Tree::with_order(order);
+    tree.set_split_policy([SplitPolicy::Middle, SplitPolicy::Append, SplitPolicy::Adaptive, SplitPolicy::BStar][(seed % 4) as usize]);
+    if seed / 4 % 2 == 1 {
+        tree.set_rebalance(Rebalance::Siblings);
+    }
+    if seed / 8 % 2 == 1 {
+        tree.set_pass(Pass::TopDown);
+    }
+    tree.set_counted(seed / 16 % 2 == 1);
+    tree
+}
+
+impl<K: Ord + Clone + Debug, V, const B: usize, A: Aggregate<K, V>, C: Count> BPlusTree<K, V, B, A, C>
+where A::Value: PartialEq + Debug {
+    // Walks the whole tree and panics on the first broken invariant: key order and separator bounds, node fill,
+    // equal leaf depth, leaf links, counts, summaries, the tracked length and leaked slab slots.
+    fn check(&self) {
+        let mut leaves = Vec::new();
+        let (len, _) = self.check_node(ROOT_ID, None, None, 0, (true, false), &mut leaves);
+        assert_eq!(self.len, len, "tracked length");
+        assert_eq!(self.nodes.len(), self.node_ids().len(), "unreachable nodes");
+        assert!(leaves.windows(2).all(|w| w[0].1 == w[1].1), "leaves at different depths");
+        for (i, &(id, _)) in leaves.iter().enumerate() {
+            let node = self.nodes.get(id).unwrap();
+            let prev = i.checked_sub(1).map(|i| leaves[i].0);
+            let next = leaves.get(i + 1).map(|l| l.0);
+            assert_eq!((node.prev, node.next), (prev, next), "links of leaf {}", id);
+        }
+    }
+
+    // `rightmost` tells whether the node is on the right edge of the tree, `root_pair` whether it is one of the two
+    // children of the root.
+    fn check_node(&self, id: NodeId, lo: Option<&K>, hi: Option<&K>, depth: usize, (rightmost, root_pair): (bool, bool), leaves: &mut Vec<(NodeId, usize)>) -> (usize, A::Value) {
+        let node = self.nodes.get(id).unwrap();
+        // Append splits leave the nodes along the right edge short until more appends arrive.
+        let relaxed = id == ROOT_ID || (rightmost && matches!(self.split_policy, SplitPolicy::Append | SplitPolicy::Adaptive));
+        // Under the two thirds minimum a root split leaves its two children half full.
+        let half = root_pair && self.two_thirds_min();
+        let (min_child, min_key) = if half { ((self.order + 2) / 2, self.order.div_ceil(2)) } else { (self.min_child(), self.min_key()) };
+        assert_eq!(node.is_root, id == ROOT_ID);
+        assert!(node.keys.windows(2).all(|w| w[0] < w[1]), "unsorted keys in {}: {:?}", id, &node.keys[..]);
+        assert!(node.keys.iter().all(|k| lo.is_none_or(|lo| k >= lo) && hi.is_none_or(|hi| k < hi)), "keys of {} outside their separators", id);
+        match &node.node_type {
+            NodeType::Internal(children) => {
+                assert_eq!(children.len(), node.keys.len() + 1);
+                assert!(children.len() <= self.max_child(), "internal {} has {} children", id, children.len());
+                assert!(relaxed || children.len() >= min_child, "internal {} has {} children", id, children.len());
+                assert!(id != ROOT_ID || children.len() >= 2, "root with a single child");
+                let (mut len, mut summary) = (0, A::identity());
+                for (i, &child) in children.iter().enumerate() {
+                    let child_lo = if i == 0 { lo } else { Some(&node.keys[i - 1]) };
+                    let child_hi = node.keys.get(i).or(hi);
+                    let (n, s) = self.check_node(child, child_lo, child_hi, depth + 1, (rightmost && i == node.keys.len(), id == ROOT_ID && children.len() == 2), leaves);
+                    if self.counted {
+                        assert_eq!(node.counts[i].size(), n, "count of child {} in {}", i, id);
+                    }
+                    if Self::AGGREGATING {
+                        assert_eq!(node.summaries[i], s, "summary of child {} in {}", i, id);
+                    }
+                    len += n;
+                    summary = A::combine(&summary, &s);
+                }
+                assert_eq!(node.counts.len(), if self.counted { children.len() } else { 0 });
+                (len, summary)
+            }
+            NodeType::Leaf(values) => {
+                assert_eq!(values.len(), node.keys.len());
+                assert!(values.len() <= self.max_key(), "leaf {} has {} keys", id, values.len());
+                assert!(relaxed || values.len() >= min_key, "leaf {} has {} keys", id, values.len());
+                leaves.push((id, depth));
+                let summary = node.keys.iter().zip(values.iter()).fold(A::identity(), |acc, (k, v)| A::combine(&acc, &A::lift(k, v)));
+                (values.len(), summary)
+            }
+        }
+    }
+}
+
+fn assert_same(tree: &Tree, map: &BTreeMap<u32, u64>) {
+    tree.check();
+    assert!(tree.iter().map(|(k, v)| (*k, *v)).eq(map.iter().map(|(k, v)| (*k, *v))));
+    assert!(tree.iter().rev().map(|(k, v)| (*k, *v)).eq(map.iter().rev().map(|(k, v)| (*k, *v))));
+}
+
+// Walks a cursor a few steps either way from `key`, changing and deleting entries on the way, and does the same to
+// `map`.
+fn cursor_both(tree: &mut Tree, map: &mut BTreeMap<u32, u64>, key: u32, rng: &mut Rng) {
+    let mut cursor = tree.cursor();
+    cursor.seek(&key);
+    let mut expected = map.range(key..).next().map(|(k, _)| *k);
+    for _ in 0..8 {
+        assert_eq!(cursor.key().copied(), expected);
+        let Some(at) = expected else {
+            cursor.seek_last();
+            expected = map.keys().next_back().copied();
+            continue
+        };
+        assert_eq!(cursor.value(), map.get(&at));
+        match rng.below(5) {
+            0 => {
+                cursor.next();
+                expected = map.range(at + 1..).next().map(|(k, _)| *k);
+            }
+            1 => {
+                cursor.prev();
+                expected = map.range(..at).next_back().map(|(k, _)| *k);
+            }
+            2 => {
+                *cursor.value_mut().unwrap() += 1000;
+                *map.get_mut(&at).unwrap() += 1000;
+            }
+            3 => {
+                let removed = cursor.delete().unwrap();
+                assert_eq!((removed.key, Some(removed.value)), (at, map.remove(&at)));
+                expected = map.range(at..).next().map(|(k, _)| *k);
+            }
+            _ => {
+                cursor.seek_first();
+                expected = map.keys().next().copied();
+            }
+        }
+    }
+}
+
+#[test]
+fn matches_btreemap() {
+    const UNIVERSE: u32 = 400;
+    for seed in 0..32 {
+        for order in [3, 4, 5, 10] {
+            let mut rng = Rng(seed * 31 + order as u64 + 1);
+            let mut tree = configured(order, seed);
+            let mut map = BTreeMap::new();
+            for step in 0..3000 {
+                let key = rng.below(UNIVERSE);
+                match rng.below(11) {
+                    0..=4 => assert_eq!(tree.insert(KeyValue { key, value: step }), map.insert(key, step).is_none()),
+                    5..=7 => assert_eq!(tree.delete(&key), map.remove(&key).is_some()),
+                    8 => assert_eq!(tree.get(&key), map.get(&key)),
+                    9 => {
+                        let end = key + rng.below(40);
+                        let expected = map.range(key..end).count();
+                        map.retain(|k, _| !(key..end).contains(k));
+                        assert_eq!(tree.remove_range(key..end), expected);
+                    }
+                    _ => cursor_both(&mut tree, &mut map, key, &mut rng),
+                }
+                if step % 97 == 0 {
+                    assert_same(&tree, &map);
+                }
+            }
+            assert_same(&tree, &map);
+            for key in 0..UNIVERSE {
+                assert_eq!(tree.get(&key), map.get(&key));
+                assert_eq!(tree.ceiling(&key).map(|(k, _)| *k), map.range(key..).next().map(|(k, _)| *k));
+                assert_eq!(tree.floor(&key), map.range(..=key).next_back());
+                assert_eq!(tree.predecessor(&key), map.range(..key).next_back());
+                assert_eq!(tree.successor(&key), map.range(key + 1..).next());
+                assert_eq!(tree.aggregate_range(key..), map.range(key..).map(|(_, v)| v).sum::<u64>());
+            }
+            assert_eq!((tree.first(), tree.last()), (map.iter().next(), map.iter().next_back()));
+            if tree.counted {
+                for key in 0..UNIVERSE {
+                    assert_eq!(tree.rank(&key), map.range(..key).count());
+                    let end = key + rng.below(60);
+                    assert_eq!(tree.count_range(key..end), map.range(key..end).count());
+                    assert_eq!(tree.count_range((Bound::Excluded(key), Bound::Included(end))), map.range((Bound::Excluded(key), Bound::Included(end))).count());
+                }
+                for (i, entry) in map.iter().enumerate() {
+                    assert_eq!(tree.select(i), Some(entry));
+                }
+                assert_eq!(tree.select(map.len()), None);
+                assert_eq!(tree.count_range(..), map.len());
+            }
+            // A second tree with other settings whose keys partly overlap, for the set operations.
+            let mut other = configured(order, seed + 5);
+            let mut other_map = BTreeMap::new();
+            for _ in 0..rng.below(300) {
+                let key = rng.below(UNIVERSE + 100);
+                other.insert(KeyValue { key, value: key as u64 + 1 });
+                other_map.insert(key, key as u64 + 1);
+            }
+            let mut merged = other_map.clone();
+            for (&key, &value) in &map {
+                merged.insert(key, other_map.get(&key).map_or(value, |b| value * 2 + b));
+            }
+            assert_same(&tree.clone().merge(other.clone(), |_, a, b| a * 2 + b), &merged);
+            let mut union = other_map.clone();
+            union.extend(map.iter().map(|(&key, &value)| (key, value)));
+            assert_same(&tree.clone().union(other.clone()), &union);
+            let intersection = map.iter().filter(|(key, _)| other_map.contains_key(key)).map(|(&key, &value)| (key, value)).collect();
+            assert_same(&tree.clone().intersection(other.clone()), &intersection);
+            let difference = map.iter().filter(|(key, _)| !other_map.contains_key(key)).map(|(&key, &value)| (key, value)).collect();
+            assert_same(&tree.clone().difference(other), &difference);
+
+            // Collected out of order and with stale duplicates in front, the last value of a key wins.
+            let mut entries: Vec<(u32, u64)> = map.iter().map(|(&key, &value)| (key, value + 1)).collect();
+            entries.extend(map.iter().rev().map(|(&key, &value)| (key, value)));
+            let collected: Tree = entries.into_iter().collect();
+            assert_same(&collected, &map);
+            assert!(collected == tree, "equal entries in differently shaped trees");
+            let mut copy = tree.clone();
+            assert_same(&copy, &map);
+            if let Some(&key) = map.keys().next() {
+                copy.delete(&key);
+                assert!(copy != tree);
+                assert_same(&tree, &map);
+            }
+            copy.insert(KeyValue { key: UNIVERSE, value: 1 });
+            assert!(copy != tree && tree.get(&UNIVERSE).is_none());
+        }
+    }
+}
+
+#[test]
+fn bstar_keeps_nodes_two_thirds_full() {
+    let mut tree = Tree::with_order(6);
+    tree.set_split_policy(SplitPolicy::BStar);
+    assert_eq!((tree.min_key(), tree.min_child()), (4, 5));
+    for key in 0..600 {
+        tree.insert(KeyValue { key, value: 1 });
+    }
+    tree.check();
+    // Deleting two of every three keys forces three siblings into two again and again.
+    for key in (0..600).filter(|key| key % 3 != 0) {
+        tree.delete(&key);
+        if key % 25 == 1 {
+            tree.check();
+        }
+    }
+    tree.check();
+    assert_eq!(tree.len(), 200);
+    tree.remove_range(40..520);
+    tree.check();
+    assert_eq!(tree.aggregate_range(..), 40);
+}
+
+// A tree of `0..n` with every setting combination, deep enough at order 3 to have several internal levels.
+fn filled(order: usize, seed: u64, keys: impl Iterator<Item = u32>) -> (Tree, BTreeMap<u32, u64>) {
+    let mut tree = configured(order, seed);
+    let mut map = BTreeMap::new();
+    for key in keys {
+        tree.insert(KeyValue { key, value: key as u64 });
+        map.insert(key, key as u64);
+    }
+    (tree, map)
+}
+
+fn remove_both(tree: &mut Tree, map: &mut BTreeMap<u32, u64>, range: (Bound<u32>, Bound<u32>)) {
+    let expected = map.range(range).count();
+    map.retain(|key, _| !range.contains(key));
+    assert_eq!(tree.remove_range(range), expected, "{:?}", range);
+    assert_same(tree, map);
+}
+
+#[test]
+fn remove_range_across_levels() {
+    for seed in 0..32 {
+        for order in [3, 4, 6] {
+            let (mut tree, mut map) = filled(order, seed, 0..2000);
+            assert!(tree.height() >= 4);
+            // Ranges whose edges part at the root, so every level below it is cut on both sides.
+            remove_both(&mut tree, &mut map, (Bound::Included(300), Bound::Excluded(1700)));
+            remove_both(&mut tree, &mut map, (Bound::Excluded(100), Bound::Included(1900)));
+            // Ranges that reach to an end of the tree, so one edge is left with nothing at all.
+            remove_both(&mut tree, &mut map, (Bound::Unbounded, Bound::Excluded(50)));
+            remove_both(&mut tree, &mut map, (Bound::Included(1950), Bound::Unbounded));
+            remove_both(&mut tree, &mut map, (Bound::Unbounded, Bound::Unbounded));
+            assert!(tree.is_empty() && tree.height() == 1);
+        }
+    }
+}
+
+#[test]
+fn remove_range_empties_an_edge() {
+    for seed in 0..32 {
+        for order in [3, 5] {
+            let (mut tree, mut map) = filled(order, seed, (0..3000).map(|key| key * 2));
+            let mut rng = Rng(seed + 7);
+            while map.len() > 40 {
+                // Start a range on the first key of a leaf, so the left edge leaf is emptied, or end it right after
+                // the last key of one, so the right edge leaf is.
+                let (leaf, _) = tree.search(&(rng.below(6000) & !1));
+                let node = tree.nodes.get(leaf).unwrap();
+                let (first, last) = (node.keys[0], node.keys[node.keys.len() - 1]);
+                let span = rng.below(400);
+                let range = if rng.below(2) == 0 {
+                    (Bound::Included(first), Bound::Excluded(first + span))
+                } else {
+                    (Bound::Included(last.saturating_sub(span)), Bound::Included(last))
+                };
+                remove_both(&mut tree, &mut map, range);
+            }
+        }
+    }
+}
+
+#[test]
+fn split_off_and_append_round_trip() {
+    for seed in 0..32 {
+        for order in [3, 5] {
+            let (mut tree, mut map) = filled(order, seed, 0..1500);
+            for key in [0, 3, 700, 1490, 1500] {
+                let mut right = tree.split_off(&key);
+                let right_map = map.split_off(&key);
+                assert_same(&tree, &map);
+                assert_same(&right, &right_map);
+                // Both ways round, so the taller tree is sometimes the lower one and sometimes the upper one.
+                if key % 2 == 0 {
+                    tree.append(&mut right);
+                } else {
+                    right.append(&mut tree);
+                    mem::swap(&mut tree, &mut right);
+                }
+                map.extend(right_map);
+                assert!(right.is_empty());
+                assert_same(&tree, &map);
+            }
+        }
+    }
+}
+
+#[test]
+fn append_across_heights_and_settings() {
+    for seed in 0..32 {
+        for (low, high) in [(1, 2000), (2000, 1), (40, 900), (900, 40), (500, 500)] {
+            let (mut tree, mut map) = filled(3, seed, 0..low);
+            // The other tree has different settings, and with another order its entries go over one by one.
+            for (order, other_seed) in [(3, seed + 5), (4, seed)] {
+                let (mut other, other_map) = filled(order, other_seed, 10_000..10_000 + high);
+                let mut upper = tree.split_off(&5_000);
+                if seed % 2 == 0 {
+                    other.append(&mut tree);
+                    mem::swap(&mut tree, &mut other);
+                } else {
+                    tree.append(&mut other);
+                }
+                assert!(other.is_empty());
+                map.extend(other_map);
+                assert_same(&tree, &map);
+                tree.append(&mut upper);
+                assert_same(&tree, &map);
+                tree.remove_range(10_000..);
+                map.retain(|&key, _| key < 10_000);
+                assert_same(&tree, &map);
+            }
+        }
+    }
+}
+
+#[test]
+fn entries_keep_aggregates() {
+    for seed in 0..32 {
+        let mut rng = Rng(seed + 3);
+        let mut tree = configured(3, seed);
+        let mut map = BTreeMap::new();
+        for step in 0..2000 {
+            let key = rng.below(300);
+            let value = rng.below(100) as u64;
+            match rng.below(4) {
+                0 => {
+                    tree.entry(key).or_insert_entry(value).modify(|v| *v += 1);
+                    *map.entry(key).or_insert(value) += 1;
+                },
+                1 => {
+                    tree.entry(key).and_modify(|v| *v *= 2).or_insert_entry(value);
+                    map.entry(key).and_modify(|v| *v *= 2).or_insert(value);
+                },
+                2 => if let Entry::Occupied(mut entry) = tree.entry(key) {
+                    assert_eq!(entry.insert(value), map.insert(key, value).unwrap());
+                },
+                _ => if let Entry::Occupied(entry) = tree.entry(key) {
+                    assert_eq!(entry.remove(), map.remove(&key).unwrap());
+                },
+            }
+            if step % 97 == 0 {
+                assert_same(&tree, &map);
+            }
+        }
+        assert_same(&tree, &map);
+        for key in (0..300).step_by(7) {
+            assert_eq!(tree.aggregate_range(key..key + 50), map.range(key..key + 50).map(|(_, v)| v).sum::<u64>());
+        }
+    }
+}
+
+#[test]
+fn retain_fixes_short_leaves() {
+    for seed in 0..32 {
+        for order in [3, 4, 6] {
+            let (mut tree, mut map) = filled(order, seed, 0..3000);
+            let mut rng = Rng(seed + 11);
+            // Sparse holes and emptied runs leave a few short leaves to fix in place, the last filters most of them.
+            for (holes, runs) in [(20, 0), (0, 6), (200, 3), (3, 0)] {
+                let drop: Vec<(u32, u32)> = (0..runs).map(|_| (rng.below(3000), rng.below(30))).map(|(start, len)| (start, start + len)).collect();
+                let keep = |key: &u32| (holes == 0 || !key.is_multiple_of(holes)) && !drop.iter().any(|&(start, end)| (start..end).contains(key));
+                tree.retain(|key, _| keep(key));
+                map.retain(|key, _| keep(key));
+                assert_same(&tree, &map);
+                assert_eq!(tree.aggregate_range(..), map.values().sum::<u64>());
+            }
+        }
+    }
+}
+
+#[test]
+fn leaked_extract_if_leaves_a_readable_tree() {
+    for seed in 0..32 {
+        for order in [3, 4, 6] {
+            let (mut tree, mut map) = filled(order, seed, 0..2000);
+            // Emptied runs take whole leaves out while the iterator is still alive.
+            let gone = |key: &u32| (500..900).contains(key) || key.is_multiple_of(7);
+            let taken: Vec<(u32, u64)> = {
+                let mut extract = tree.extract_if(|key, value| {
+                    *value += 1;
+                    gone(key)
+                });
+                let taken = extract.by_ref().take(450).collect();
+                mem::forget(extract);
+                taken
+            };
+            let last = taken.last().unwrap().0;
+            for value in map.range_mut(..=last).map(|(_, value)| value) {
+                *value += 1;
+            }
+            map.retain(|key, _| *key > last || !gone(key));
+            assert!(tree.iter().map(|(key, value)| (*key, *value)).eq(map.iter().map(|(key, value)| (*key, *value))));
+            assert!(tree.iter().rev().map(|(key, _)| *key).eq(map.keys().rev().copied()));
+            assert_eq!(tree.len(), map.len());
+            for key in (0..2100).step_by(13) {
+                assert_eq!(tree.ceiling(&key), map.range(key..).next());
+                assert_eq!(tree.floor(&key), map.range(..=key).next_back());
+                assert_eq!(tree.get(&key), map.get(&key));
+            }
+            if tree.counted {
+                assert_eq!(tree.count_range(..), map.len());
+            }
+            assert_eq!(tree.aggregate_range(300..1000), map.range(300..1000).map(|(_, value)| value).sum::<u64>());
+            // The next filter that runs to the end balances it again.
+            tree.retain(|_, _| true);
+            assert_same(&tree, &map);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "count_range needs counted mode")]
+fn count_range_needs_counted_mode() {
+    let (tree, _) = filled(4, 0, 0..100);
+    tree.count_range(..);
+}
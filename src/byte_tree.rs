@@ -0,0 +1,643 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+use crate::btrees::TreePath;
+use crate::page::{self, Page, MAX_PAGE_SIZE, PAGE_HEADER, SLOT_SIZE};
+use crate::slab::{NodeId, Slab};
+
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+const MIN_PAGE_SIZE: usize = 128;
+
+// The root always lives under this id, like in `BPlusTree`.
+const ROOT_ID: NodeId = 0;
+
+// A B+ tree over byte string keys and values of any length, stored in slotted pages (see page.rs).
+//
+// Nodes don't hold a fixed number of entries here: a page is full when its bytes run out. So a split cuts an
+// overflowing page where half of its bytes lie on either side instead of in the middle of its entries, and a page
+// underflows when less than a quarter of its room is taken. A single entry may take at most that quarter, which
+// makes sure a page cut in two by bytes doesn't leave an underflowing half behind, unless the keys of a half share a
+// longer prefix than those of the whole page and shrink. Bigger values are spilled into a
+// chain of overflow pages and the leaf only keeps a reference to it, so just the key is limited.
+#[derive(Clone, Debug)]
+pub struct BytesTree {
+    page_size: usize,
+    len: usize,
+    pages: Slab<Page>,
+}
+
+// Returned by `BytesTree::insert` for a key that doesn't fit in a page of the tree's size. `max` is the longest key
+// the tree takes, see `BytesTree::max_key_len`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyTooLarge {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for KeyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key of {} bytes is longer than the {} bytes a page allows", self.len, self.max)
+    }
+}
+
+impl Error for KeyTooLarge {}
+
+// Where to cut a run of cells that is too big for one page: the cut where both halves fit and the bigger one is
+// as small as it can be. Each half is measured with the prefix its own keys share, which can be longer than the one
+// of the whole run. For leaves it is the number of cells going left. For internal pages it is the index of the cell
+// that moves up to the parent, and both sides keep at least one cell. None when no cut fits.
+fn cut(cells: &[Vec<u8>], is_leaf: bool, page_size: usize) -> Option<usize> {
+    let keys: Vec<&[u8]> = cells.iter().map(|cell| page::cell_key(cell, is_leaf)).collect();
+    let mut sums = vec![0];
+    for cell in cells {
+        sums.push(sums.last().unwrap() + SLOT_SIZE + cell.len());
+    }
+    // Bytes cells[from..to] take in a page of their own. Sorted keys share what the first and the last one share.
+    let size = |from: usize, to: usize| {
+        let prefix = page::common_prefix(keys[from], keys[to - 1]);
+        PAGE_HEADER + prefix + sums[to] - sums[from] - (to - from) * prefix
+    };
+    let n = cells.len();
+    let (skip, cuts) = if is_leaf { (0, 1..n) } else { (1, 1..n.saturating_sub(1)) };
+    cuts.map(|at| (size(0, at).max(size(at + skip, n)), at))
+        .filter(|&(bigger, _)| bigger <= page_size)
+        .min()
+        .map(|(_, at)| at)
+}
+
+// The shortest key above `left` and not above `right`, to separate two leaves with (suffix truncation).
+fn separator(left: &[u8], right: &[u8]) -> Vec<u8> {
+    right[..page::common_prefix(left, right) + 1].to_vec()
+}
+
+// Cuts `cells` as `cut` says. Returns the cells of the left page, the separator for the parent, the left page's new
+// rightmost child for internal pages, and the cells of the right page.
+type Halves = (Vec<Vec<u8>>, Vec<u8>, Option<NodeId>, Vec<Vec<u8>>);
+
+fn halve(mut cells: Vec<Vec<u8>>, is_leaf: bool, page_size: usize) -> Option<Halves> {
+    let mut right = cells.split_off(cut(&cells, is_leaf, page_size)?);
+    if is_leaf {
+        let separator = separator(page::cell_key(cells.last().unwrap(), true), page::cell_key(&right[0], true));
+        Some((cells, separator, None, right))
+    } else {
+        let middle = right.remove(0);
+        Some((cells, page::cell_key(&middle, false).to_vec(), Some(page::cell_child(&middle)), right))
+    }
+}
+
+impl Default for BytesTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BytesTree {
+    pub fn new() -> Self {
+        Self::with_page_size(DEFAULT_PAGE_SIZE)
+    }
+
+    pub fn with_page_size(page_size: usize) -> Self {
+        assert!((MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size), "page size must be between {} and {}", MIN_PAGE_SIZE, MAX_PAGE_SIZE);
+        let mut pages = Slab::new();
+        pages.alloc(Page::new(page_size, true));
+        BytesTree { page_size, len: 0, pages }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The most bytes a cell and its slot may take: a quarter of the room in a page.
+    fn max_cell(&self) -> usize {
+        (self.page_size - PAGE_HEADER) / 4
+    }
+
+    // Whether `used` bytes are more than a page holds.
+    fn is_overflow(&self, used: usize) -> bool {
+        used > self.page_size
+    }
+
+    fn is_underflow(&self, page: &Page) -> bool {
+        page.used() < PAGE_HEADER + self.max_cell()
+    }
+
+    // The leaf that covers `key`.
+    fn leaf_for(&self, key: &[u8]) -> NodeId {
+        let mut id = ROOT_ID;
+        loop {
+            let page = self.pages.get(id).unwrap();
+            if page.is_leaf() {
+                return id
+            }
+            id = page.child(page.child_index(key));
+        }
+    }
+
+    // Refills `path` with every page from the root down to the leaf that covers `key`, and returns that leaf.
+    fn search_path(&self, key: &[u8], path: &mut TreePath) -> NodeId {
+        path.clear();
+        let (mut id, mut index) = (ROOT_ID, 0);
+        loop {
+            path.push(id, index);
+            let page = self.pages.get(id).unwrap();
+            if page.is_leaf() {
+                return id
+            }
+            index = page.child_index(key);
+            id = page.child(index);
+        }
+    }
+
+    // The value of `key`. One that was spilled is read back from its overflow pages into a new buffer.
+    pub fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>> {
+        let page = self.pages.get(self.leaf_for(key)).unwrap();
+        page.search(key).ok().map(|i| self.value_at(page, i))
+    }
+
+    fn value_at<'a>(&'a self, page: &'a Page, i: usize) -> Cow<'a, [u8]> {
+        let Some((len, first)) = page.spilled(i) else { return Cow::Borrowed(page.value(i)) };
+        let mut value = Vec::with_capacity(len);
+        let mut next = Some(first);
+        while let Some(id) = next {
+            let page = self.pages.get(id).unwrap();
+            value.extend_from_slice(page.data());
+            next = page.next();
+        }
+        Cow::Owned(value)
+    }
+
+    // The leaf cell for an entry. A value that would make it bigger than `max_cell` is written to overflow pages.
+    fn entry_cell(&mut self, key: &[u8], value: &[u8]) -> Vec<u8> {
+        if 4 + key.len() + value.len() + SLOT_SIZE <= self.max_cell() {
+            return page::leaf_cell(key, value)
+        }
+        // The chain is built from its end, so each page can point to the one after it.
+        let mut next = None;
+        for chunk in value.chunks(self.page_size - PAGE_HEADER).rev() {
+            next = Some(self.pages.alloc(Page::overflow(self.page_size, chunk, next)));
+        }
+        page::spilled_cell(key, value.len(), next.unwrap())
+    }
+
+    // Frees the overflow pages of cell `i` of `leaf`, if its value was spilled.
+    fn free_overflow(&mut self, leaf: NodeId, i: usize) {
+        let mut next = self.pages.get(leaf).unwrap().spilled(i).map(|(_, first)| first);
+        while let Some(id) = next {
+            next = self.pages.free(id).next();
+        }
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    // The longest key `insert` takes. It has to fit next to the reference to a spilled value, and in an internal cell.
+    pub fn max_key_len(&self) -> usize {
+        self.max_cell() - 12 - SLOT_SIZE
+    }
+
+    // Returns false when the key was already present; its value is overwritten in that case. A key longer than
+    // `max_key_len` is refused and the tree is left as it was.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<bool, KeyTooLarge> {
+        if key.len() > self.max_key_len() {
+            return Err(KeyTooLarge { len: key.len(), max: self.max_key_len() })
+        }
+        let mut path = TreePath::new();
+        let leaf = self.search_path(key, &mut path);
+        let found = self.pages.get(leaf).unwrap().search(key);
+        if let Ok(index) = found {
+            self.free_overflow(leaf, index);
+        }
+        let cell = self.entry_cell(key, value);
+        match found {
+            Ok(index) => {
+                self.pages.get_mut(leaf).unwrap().remove_cell(index);
+                // A shorter value can leave the leaf underflowing.
+                if !self.put_cell(&mut path, index, cell) {
+                    self.rebalance_path(path);
+                }
+                Ok(false)
+            },
+            Err(index) => {
+                self.put_cell(&mut path, index, cell);
+                self.len += 1;
+                Ok(true)
+            },
+        }
+    }
+
+    // Puts `cell` in at slot `index` of the page at the end of `path`. A page it doesn't fit in is split, which puts a
+    // cell for the new page into the parent, and so on up. Returns whether anything was split; when nothing was,
+    // `path` is left as it was.
+    fn put_cell(&mut self, path: &mut TreePath, mut index: usize, mut cell: Vec<u8>) -> bool {
+        let mut split = false;
+        loop {
+            let (id, position) = path.last().unwrap();
+            if !self.is_overflow(self.pages.get(id).unwrap().used_with(&cell)) {
+                self.pages.get_mut(id).unwrap().insert_cell(index, &cell);
+                return split
+            }
+            split = true;
+            path.pop();
+            let Some((left, separator)) = self.split(id, index, cell) else { return true };
+            cell = page::internal_cell(left, &separator);
+            index = position;
+        }
+    }
+
+    // Splits page `id`, which `cell` didn't fit in at `index`, in two by bytes. The right half stays in `id` so the
+    // parent's pointer to it remains valid, and the left half moves to a new page. Returns that page with the
+    // separator the parent needs for it. The root is split into two new pages instead, so it keeps its id, and None
+    // is returned.
+    fn split(&mut self, id: NodeId, index: usize, cell: Vec<u8>) -> Option<(NodeId, Vec<u8>)> {
+        let page = self.pages.get(id).unwrap();
+        let is_leaf = page.is_leaf();
+        let (prev, rightmost) = if is_leaf { (page.prev(), None) } else { (None, Some(page.child(page.len()))) };
+        let mut cells = page.cells();
+        cells.insert(index, cell);
+        let (left_cells, separator, left_rightmost, right_cells) = halve(cells, is_leaf, self.page_size).expect("no cut of the page fits");
+
+        let mut left = Page::new(self.page_size, is_leaf);
+        left.set_cells(&left_cells);
+        if let Some(child) = left_rightmost {
+            left.set_child(left.len(), child);
+        }
+
+        if id == ROOT_ID {
+            let mut right = Page::new(self.page_size, is_leaf);
+            right.set_cells(&right_cells);
+            if let Some(child) = rightmost {
+                right.set_child(right.len(), child);
+            }
+            let left_id = self.pages.alloc(left);
+            let right_id = self.pages.alloc(right);
+            if is_leaf {
+                self.pages.get_mut(left_id).unwrap().set_next(Some(right_id));
+                self.pages.get_mut(right_id).unwrap().set_prev(Some(left_id));
+            }
+            let mut root = Page::new(self.page_size, false);
+            root.insert_cell(0, &page::internal_cell(left_id, &separator));
+            root.set_child(1, right_id);
+            *self.pages.get_mut(ROOT_ID).unwrap() = root;
+            return None
+        }
+
+        let page = self.pages.get_mut(id).unwrap();
+        page.set_cells(&right_cells);
+        if is_leaf {
+            left.set_prev(prev);
+            left.set_next(Some(id));
+        }
+        let left_id = self.pages.alloc(left);
+        if is_leaf {
+            self.pages.get_mut(id).unwrap().set_prev(Some(left_id));
+            if let Some(prev) = prev {
+                self.pages.get_mut(prev).unwrap().set_next(Some(left_id));
+            }
+        }
+        Some((left_id, separator))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> bool {
+        let mut path = TreePath::new();
+        let leaf = self.search_path(key, &mut path);
+        let Ok(index) = self.pages.get(leaf).unwrap().search(key) else { return false };
+        self.free_overflow(leaf, index);
+        self.pages.get_mut(leaf).unwrap().remove_cell(index);
+        self.len -= 1;
+        self.rebalance_path(path);
+        true
+    }
+
+    // Fixes the pages on `path` from its end up after the last one shrank. An underflowing page is merged with a
+    // sibling when the two fit in one page, otherwise the two share their cells out by bytes. Merging takes a cell
+    // out of the parent and sharing can give it a shorter separator, so the parent is looked at next; a longer
+    // separator can split the parent instead, which ends the walk.
+    fn rebalance_path(&mut self, mut path: TreePath) {
+        while let Some((id, position)) = path.pop() {
+            let Some((parent, _)) = path.last() else {
+                self.collapse_root();
+                return
+            };
+            if !self.is_underflow(self.pages.get(id).unwrap()) {
+                return
+            }
+            let parent_page = self.pages.get(parent).unwrap();
+            // The page is paired with its right sibling, or with its left one when it is the rightmost child.
+            let separator = if position < parent_page.len() { position } else { position - 1 };
+            let (left, right) = (parent_page.child(separator), parent_page.child(separator + 1));
+            let divider = parent_page.key(separator).into_owned();
+            let (left_page, right_page) = (self.pages.get(left).unwrap(), self.pages.get(right).unwrap());
+            let is_leaf = left_page.is_leaf();
+            let prev = left_page.prev();
+            let mut cells = left_page.cells();
+            if !is_leaf {
+                cells.push(page::internal_cell(left_page.child(left_page.len()), &divider));
+            }
+            cells.extend(right_page.cells());
+
+            if !self.is_overflow(page::occupancy(&cells, is_leaf)) {
+                // Merges into the right page, which the parent's next cell or rightmost child already points to.
+                let right_page = self.pages.get_mut(right).unwrap();
+                right_page.set_cells(&cells);
+                if is_leaf {
+                    right_page.set_prev(prev);
+                    if let Some(prev) = prev {
+                        self.pages.get_mut(prev).unwrap().set_next(Some(right));
+                    }
+                }
+                self.pages.free(left);
+                self.pages.get_mut(parent).unwrap().remove_cell(separator);
+                continue
+            }
+
+            // Without a cut that fits, the two pages already are the best split there is and stay as they are.
+            let Some((left_cells, new_divider, left_rightmost, right_cells)) = halve(cells, is_leaf, self.page_size) else { return };
+            let (left_page, right_page) = self.pages.get_pair_mut(left, right);
+            left_page.set_cells(&left_cells);
+            if let Some(child) = left_rightmost {
+                left_page.set_child(left_page.len(), child);
+            }
+            right_page.set_cells(&right_cells);
+            self.pages.get_mut(parent).unwrap().remove_cell(separator);
+            if self.put_cell(&mut path, separator, page::internal_cell(left, &new_divider)) {
+                return
+            }
+        }
+    }
+
+    // A root left without keys hands its place to its only child.
+    fn collapse_root(&mut self) {
+        let root = self.pages.get(ROOT_ID).unwrap();
+        if root.is_leaf() || !root.is_empty() {
+            return
+        }
+        let child = root.child(0);
+        let page = self.pages.free(child);
+        *self.pages.get_mut(ROOT_ID).unwrap() = page;
+    }
+
+    // All entries in key order.
+    pub fn iter(&self) -> Iter<'_> {
+        let mut id = ROOT_ID;
+        loop {
+            let page = self.pages.get(id).unwrap();
+            if page.is_leaf() {
+                return Iter { tree: self, leaf: Some(id), index: 0 }
+            }
+            id = page.child(0);
+        }
+    }
+}
+
+// Iterator over `(key, value)` pairs returned by `BytesTree::iter`, following the leaf chain.
+pub struct Iter<'a> {
+    tree: &'a BytesTree,
+    leaf: Option<NodeId>,
+    index: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (Cow<'a, [u8]>, Cow<'a, [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let page = self.tree.pages.get(self.leaf?).unwrap();
+            if self.index < page.len() {
+                self.index += 1;
+                return Some((page.key(self.index - 1), self.tree.value_at(page, self.index - 1)))
+            }
+            self.leaf = page.next();
+            self.index = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    // Small xorshift generator, so every run sees the same keys.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // Between `min` and `max` bytes out of a small alphabet, so keys share long prefixes.
+        fn bytes(&mut self, min: usize, max: usize) -> Vec<u8> {
+            let len = min + self.next() as usize % (max - min + 1);
+            (0..len).map(|_| b'a' + (self.next() % 4) as u8).collect()
+        }
+    }
+
+    impl BytesTree {
+        // Walks the pages under `id` and panics on keys outside their separators or a page over its size. Returns
+        // the number of pages reached, overflow pages included.
+        fn check_page(&self, id: NodeId, lo: Option<&[u8]>, hi: Option<&[u8]>) -> usize {
+            let page = self.pages.get(id).unwrap();
+            assert!(page.used() <= self.page_size, "page {} takes {} bytes", id, page.used());
+            let keys: Vec<Vec<u8>> = (0..page.len()).map(|i| page.key(i).into_owned()).collect();
+            assert!(keys.windows(2).all(|w| w[0] < w[1]), "unsorted keys in {}", id);
+            assert!(keys.iter().all(|key| lo.is_none_or(|lo| &key[..] >= lo) && hi.is_none_or(|hi| &key[..] < hi)), "keys of {} outside their separators", id);
+            assert!(keys.iter().all(|key| key.starts_with(page.prefix())), "keys of {} without its prefix", id);
+            if page.is_leaf() {
+                let chains: usize = (0..page.len()).filter_map(|i| page.spilled(i)).map(|(_, first)| self.chain(first).len()).sum();
+                return 1 + chains
+            }
+            (0..=page.len())
+                .map(|i| {
+                    let child_lo = if i == 0 { lo } else { Some(&keys[i - 1][..]) };
+                    self.check_page(page.child(i), child_lo, keys.get(i).map(|key| &key[..]).or(hi))
+                })
+                .sum::<usize>() + 1
+        }
+
+        fn check(&self) {
+            assert_eq!(self.check_page(ROOT_ID, None, None), self.pages.len(), "unreachable pages");
+            assert_eq!(self.iter().count(), self.len);
+        }
+
+        // The pages of the overflow chain starting at `first`.
+        fn chain(&self, first: NodeId) -> Vec<NodeId> {
+            let mut pages = Vec::new();
+            let mut next = Some(first);
+            while let Some(id) = next {
+                pages.push(id);
+                next = self.pages.get(id).unwrap().next();
+            }
+            pages
+        }
+    }
+
+    fn assert_same(tree: &BytesTree, map: &BTreeMap<Vec<u8>, Vec<u8>>) {
+        tree.check();
+        assert!(tree.iter().map(|(key, value)| (key.into_owned(), value.into_owned())).eq(map.clone()));
+        for key in map.keys() {
+            assert_eq!(tree.get(key).as_deref(), map.get(key).map(Vec::as_slice));
+        }
+    }
+
+    #[test]
+    fn matches_btreemap() {
+        for page_size in [MIN_PAGE_SIZE, 256, 1024] {
+            let mut rng = Rng(page_size as u64);
+            let mut tree = BytesTree::with_page_size(page_size);
+            let mut map = BTreeMap::new();
+            for step in 0..3000 {
+                let key = rng.bytes(1, 8);
+                if rng.next().is_multiple_of(3) {
+                    assert_eq!(tree.delete(&key), map.remove(&key).is_some());
+                } else {
+                    let value = rng.bytes(0, 12);
+                    assert_eq!(tree.insert(&key, &value), Ok(map.insert(key, value).is_none()));
+                }
+                if step % 97 == 0 {
+                    assert_same(&tree, &map);
+                }
+            }
+            assert_same(&tree, &map);
+        }
+    }
+
+    #[test]
+    fn key_too_large_leaves_the_tree_unchanged() {
+        let mut tree = BytesTree::with_page_size(256);
+        let max = tree.max_key_len();
+        for i in 0..100u32 {
+            tree.insert(&i.to_be_bytes(), b"value").unwrap();
+        }
+        let pages = tree.pages.len();
+        let long = vec![b'k'; max + 1];
+        assert_eq!(tree.insert(&long, b"value"), Err(KeyTooLarge { len: max + 1, max }));
+        assert_eq!(tree.insert(&long, &[0; 1000]), Err(KeyTooLarge { len: max + 1, max }), "nothing spilled for a refused key");
+        assert_eq!((tree.len(), tree.pages.len()), (100, pages));
+        assert!(!tree.contains_key(&long));
+        tree.check();
+
+        // The longest key the tree takes still fits, also with a value that spills and in the internal pages above.
+        for i in 0..20u8 {
+            let mut key = vec![i; max];
+            key[max - 1] = 0xff;
+            assert_eq!(tree.insert(&key, &vec![i; 200 * (i as usize % 2)]), Ok(true));
+        }
+        tree.check();
+        assert_eq!(tree.len(), 120);
+        assert_eq!(KeyTooLarge { len: 300, max: 200 }.to_string(), "key of 300 bytes is longer than the 200 bytes a page allows");
+    }
+
+    #[test]
+    fn spilled_values_live_in_overflow_chains() {
+        let mut tree = BytesTree::with_page_size(256);
+        let room = 256 - PAGE_HEADER;
+        let mut map = BTreeMap::new();
+        // Right at the limit of a leaf cell with a four byte key, just over it, a page's worth, a little more and many
+        // pages.
+        let inline = tree.max_cell() - SLOT_SIZE - 4 - 4;
+        for (i, len) in [inline, inline + 1, room, room + 1, 10 * room + 7].into_iter().enumerate() {
+            let key = format!("key{}", i).into_bytes();
+            let value: Vec<u8> = (0..len).map(|j| (j * 7 + i) as u8).collect();
+            tree.insert(&key, &value).unwrap();
+            let leaf = tree.pages.get(tree.leaf_for(&key)).unwrap();
+            let index = leaf.search(&key).unwrap();
+            match leaf.spilled(index) {
+                None => assert_eq!(len, inline, "only the value at the limit stays in its leaf"),
+                Some((spilled, first)) => {
+                    assert_ne!(len, inline, "a value at the limit stays in its leaf");
+                    assert_eq!(spilled, len);
+                    assert_eq!(tree.chain(first).len(), len.div_ceil(room));
+                },
+            }
+            map.insert(key, value);
+        }
+        assert_same(&tree, &map);
+
+        // Enough small entries around them to split and merge the leaves that hold the references.
+        let mut rng = Rng(3);
+        for _ in 0..400 {
+            let key = rng.bytes(3, 6);
+            let value = rng.bytes(0, 8);
+            tree.insert(&key, &value).unwrap();
+            map.insert(key, value);
+        }
+        assert_same(&tree, &map);
+
+        // Overwriting frees the old chain whether the new value spills or not.
+        let pages = tree.pages.len();
+        tree.insert(b"key4", &vec![1; 3 * room]).unwrap();
+        map.insert(b"key4".to_vec(), vec![1; 3 * room]);
+        assert_eq!(tree.pages.len(), pages - 11 + 3);
+        tree.insert(b"key3", b"short").unwrap();
+        map.insert(b"key3".to_vec(), b"short".to_vec());
+        assert_same(&tree, &map);
+
+        for key in map.keys().cloned().collect::<Vec<_>>() {
+            assert!(tree.delete(&key));
+            map.remove(&key);
+            if map.len() % 50 == 0 {
+                assert_same(&tree, &map);
+            }
+        }
+        assert_eq!(tree.pages.len(), 1, "every chain was freed");
+    }
+
+    #[test]
+    fn separators_are_the_shortest_dividing_keys() {
+        assert_eq!(separator(b"https://a.com/x/17", b"https://a.com/x/2"), b"https://a.com/x/2");
+        assert_eq!(separator(b"https://a.com/x/17", b"https://a.com/x/18000"), b"https://a.com/x/18");
+        assert_eq!(separator(b"ab", b"abc"), b"abc");
+
+        let mut tree = BytesTree::with_page_size(512);
+        let url = |i: u32| format!("https://example.com/articles/{:06}/comments", i).into_bytes();
+        let mut rng = Rng(7);
+        let mut map = BTreeMap::new();
+        for _ in 0..2000 {
+            let key = url(rng.next() as u32 % 100_000);
+            tree.insert(&key, b"v").unwrap();
+            map.insert(key, b"v".to_vec());
+        }
+        assert_same(&tree, &map);
+
+        let root = tree.pages.get(ROOT_ID).unwrap();
+        assert!(!root.is_leaf());
+        // Every separator in the level above the leaves is a cut down first key of its right leaf, one byte longer
+        // than what it shares with the last key of its left leaf.
+        let (mut internal, mut leaves) = (vec![ROOT_ID], 0);
+        while let Some(id) = internal.pop() {
+            let page = tree.pages.get(id).unwrap();
+            if !tree.pages.get(page.child(0)).unwrap().is_leaf() {
+                internal.extend((0..=page.len()).map(|i| page.child(i)));
+                continue
+            }
+            for i in 0..=page.len() {
+                let leaf = tree.pages.get(page.child(i)).unwrap();
+                // Leaves that came out of a split keep the shared "https://example.com/articles/" once.
+                assert!(leaf.prefix().starts_with(b"https://example.com/articles/"));
+                leaves += 1;
+                if i < page.len() {
+                    let right = tree.pages.get(page.child(i + 1)).unwrap();
+                    let (last, first) = (leaf.key(leaf.len() - 1), right.key(0));
+                    assert_eq!(page.key(i), separator(&last, &first));
+                    assert!(page.key(i).len() < first.len());
+                }
+            }
+        }
+        // Even the average leaf holds more entries than a full page would without the prefix.
+        let uncompressed = (512 - PAGE_HEADER) / (SLOT_SIZE + page::leaf_cell(&url(0), b"v").len());
+        assert!(map.len() > leaves * (uncompressed + 3), "{} entries in {} leaves", map.len(), leaves);
+    }
+}
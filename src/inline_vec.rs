@@ -0,0 +1,382 @@
+use std::fmt::{self, Debug};
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::{ptr, slice};
+
+// A vector with a fixed capacity `N` that stores its elements inline, so a node's keys sit inside the node itself
+// instead of behind a separate heap allocation. Elements `0..len` are initialized, the rest of `buf` is not.
+#[repr(C)]
+pub struct InlineVec<T, const N: usize> {
+    len: u32,
+    buf: [MaybeUninit<T>; N],
+}
+
+impl<T, const N: usize> InlineVec<T, N> {
+    pub fn new() -> Self {
+        assert!(N <= u32::MAX as usize, "capacity does not fit the length field");
+        InlineVec { len: 0, buf: [const { MaybeUninit::uninit() }; N] }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn as_ptr(&self) -> *const T {
+        self.buf.as_ptr() as *const T
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.as_mut_ptr() as *mut T
+    }
+
+    pub fn push(&mut self, value: T) {
+        let len = self.len();
+        assert!(len < N, "InlineVec is full");
+        self.buf[len].write(value);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // SAFETY: the slot was initialized and is now outside `0..len`, so it is read exactly once.
+        Some(unsafe { self.buf[self.len()].assume_init_read() })
+    }
+
+    pub fn insert(&mut self, index: usize, value: T) {
+        let len = self.len();
+        assert!(index <= len, "insertion index {} is out of bounds", index);
+        assert!(len < N, "InlineVec is full");
+        // SAFETY: `index..len` is initialized and there is room for one more element behind it.
+        unsafe {
+            let p = self.as_mut_ptr().add(index);
+            ptr::copy(p, p.add(1), len - index);
+            ptr::write(p, value);
+        }
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        let len = self.len();
+        assert!(index < len, "removal index {} is out of bounds", index);
+        // SAFETY: the element at `index` is read once and the tail is shifted over it.
+        unsafe {
+            let p = self.as_mut_ptr().add(index);
+            let value = ptr::read(p);
+            ptr::copy(p.add(1), p, len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        while self.len() > len {
+            self.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    // Moves the elements from `at` onwards into a new vector.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let len = self.len();
+        assert!(at <= len, "split index {} is out of bounds", at);
+        let mut other = Self::new();
+        // SAFETY: `at..len` is initialized and ownership moves to `other` by shortening `self`.
+        unsafe {
+            ptr::copy_nonoverlapping(self.as_ptr().add(at), other.as_mut_ptr(), len - at);
+        }
+        other.len = (len - at) as u32;
+        self.len = at as u32;
+        other
+    }
+
+    // Moves the first `n` elements into a new vector and shifts the rest to the front.
+    pub fn split_front(&mut self, n: usize) -> Self {
+        let len = self.len();
+        assert!(n <= len, "split index {} is out of bounds", n);
+        let mut other = Self::new();
+        // SAFETY: `0..n` moves to `other`, then the still initialized `n..len` is shifted over it.
+        unsafe {
+            ptr::copy_nonoverlapping(self.as_ptr(), other.as_mut_ptr(), n);
+            ptr::copy(self.as_ptr().add(n), self.as_mut_ptr(), len - n);
+        }
+        other.len = n as u32;
+        self.len = (len - n) as u32;
+        other
+    }
+
+    // Moves every element of `other` to the back of `self`.
+    pub fn append(&mut self, other: &mut Self) {
+        let (len, moved) = (self.len(), other.len());
+        assert!(len + moved <= N, "InlineVec is full");
+        // SAFETY: the moved elements are forgotten by `other` once copied.
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().add(len), moved);
+        }
+        self.len += other.len;
+        other.len = 0;
+    }
+
+    // Moves every element of `other` to the front of `self`.
+    pub fn prepend(&mut self, other: &mut Self) {
+        let (len, moved) = (self.len(), other.len());
+        assert!(len + moved <= N, "InlineVec is full");
+        // SAFETY: the existing elements are shifted back first, then the moved ones are forgotten by `other`.
+        unsafe {
+            ptr::copy(self.as_ptr(), self.as_mut_ptr().add(moved), len);
+            ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr(), moved);
+        }
+        self.len += other.len;
+        other.len = 0;
+    }
+}
+
+impl<T, const N: usize> Default for InlineVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for InlineVec<T, N> {
+    fn drop(&mut self) {
+        // SAFETY: exactly `0..len` is initialized.
+        unsafe { ptr::drop_in_place(self.deref_mut() as *mut [T]) }
+    }
+}
+
+impl<T, const N: usize> Deref for InlineVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: exactly `0..len` is initialized.
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for InlineVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: exactly `0..len` is initialized.
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len()) }
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for InlineVec<T, N> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: Debug, const N: usize> Debug for InlineVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T, const N: usize> Extend<T> for InlineVec<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for InlineVec<T, N> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = Self::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a InlineVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for InlineVec<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let vec = ManuallyDrop::new(self);
+        // SAFETY: `vec` is never dropped, so the buffer and its initialized prefix now belong to the iterator.
+        let buf = unsafe { ptr::read(&vec.buf) };
+        IntoIter { buf, start: 0, end: vec.len() }
+    }
+}
+
+pub struct IntoIter<T, const N: usize> {
+    buf: [MaybeUninit<T>; N],
+    start: usize,
+    end: usize,
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.start += 1;
+        // SAFETY: `start..end` is initialized and each slot is yielded once.
+        Some(unsafe { self.buf[self.start - 1].assume_init_read() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.end - self.start, Some(self.end - self.start))
+    }
+}
+
+impl<T, const N: usize> DoubleEndedIterator for IntoIter<T, N> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.end -= 1;
+        // SAFETY: `start..end` is initialized and each slot is yielded once.
+        Some(unsafe { self.buf[self.end].assume_init_read() })
+    }
+}
+
+impl<T, const N: usize> ExactSizeIterator for IntoIter<T, N> {}
+
+impl<T, const N: usize> Drop for IntoIter<T, N> {
+    fn drop(&mut self) {
+        for slot in &mut self.buf[self.start..self.end] {
+            // SAFETY: the slots that were not yielded are still initialized.
+            unsafe { slot.assume_init_drop() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InlineVec;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Counts how many times values sharing the same counter were dropped.
+    struct Tracked(u32, Rc<Cell<usize>>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.1.set(self.1.get() + 1);
+        }
+    }
+
+    fn tracked<const N: usize>(values: std::ops::Range<u32>, drops: &Rc<Cell<usize>>) -> InlineVec<Tracked, N> {
+        values.map(|v| Tracked(v, drops.clone())).collect()
+    }
+
+    fn values<const N: usize>(vec: &InlineVec<Tracked, N>) -> Vec<u32> {
+        vec.iter().map(|t| t.0).collect()
+    }
+
+    #[test]
+    fn insert_and_remove_shift_the_tail() {
+        let mut vec: InlineVec<u32, 8> = (0..5).collect();
+        vec.insert(0, 10);
+        vec.insert(3, 11);
+        vec.insert(vec.len(), 12);
+        assert_eq!(&vec[..], &[10, 0, 1, 11, 2, 3, 4, 12]);
+        assert_eq!(vec.remove(0), 10);
+        assert_eq!(vec.remove(2), 11);
+        assert_eq!(vec.remove(vec.len() - 1), 12);
+        assert_eq!(&vec[..], &[0, 1, 2, 3, 4]);
+        assert_eq!(vec.pop(), Some(4));
+        assert_eq!(vec.len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "InlineVec is full")]
+    fn insert_into_full_vector_panics() {
+        let mut vec: InlineVec<u32, 2> = (0..2).collect();
+        vec.insert(1, 5);
+    }
+
+    #[test]
+    fn split_and_join_keep_order() {
+        let mut vec: InlineVec<u32, 8> = (0..6).collect();
+        let mut back = vec.split_off(4);
+        let mut front = vec.split_front(1);
+        assert_eq!((&front[..], &vec[..], &back[..]), (&[0][..], &[1, 2, 3][..], &[4, 5][..]));
+        vec.append(&mut back);
+        vec.prepend(&mut front);
+        assert!(front.is_empty() && back.is_empty());
+        assert_eq!(&vec[..], &[0, 1, 2, 3, 4, 5]);
+        assert!(vec.split_off(6).is_empty());
+        assert!(vec.split_front(0).is_empty());
+        assert_eq!(vec.len(), 6);
+    }
+
+    #[test]
+    fn moved_elements_are_dropped_once() {
+        let drops = Rc::new(Cell::new(0));
+        {
+            let mut vec: InlineVec<Tracked, 8> = tracked(0..6, &drops);
+            let removed = vec.remove(2);
+            assert_eq!(removed.0, 2);
+            drop(removed);
+            assert_eq!(drops.get(), 1);
+            vec.insert(1, Tracked(20, drops.clone()));
+            let mut back = vec.split_off(4);
+            let mut front = vec.split_front(2);
+            assert_eq!((values(&front), values(&vec), values(&back)), (vec![0, 20], vec![1, 3], vec![4, 5]));
+            vec.prepend(&mut back);
+            vec.append(&mut front);
+            assert_eq!(values(&vec), vec![4, 5, 1, 3, 0, 20]);
+            assert_eq!(drops.get(), 1);
+            vec.truncate(4);
+            assert_eq!(drops.get(), 3);
+        }
+        assert_eq!(drops.get(), 7);
+    }
+
+    #[test]
+    fn into_iter_drops_what_it_does_not_yield() {
+        let drops = Rc::new(Cell::new(0));
+        let mut iter = tracked::<8>(0..6, &drops).into_iter();
+        assert_eq!(iter.len(), 6);
+        assert_eq!(iter.next().map(|t| t.0), Some(0));
+        assert_eq!(iter.next_back().map(|t| t.0), Some(5));
+        assert_eq!(drops.get(), 2);
+        assert_eq!(iter.len(), 4);
+        drop(iter);
+        assert_eq!(drops.get(), 6);
+
+        let drops = Rc::new(Cell::new(0));
+        let all: Vec<u32> = tracked::<8>(0..5, &drops).into_iter().rev().map(|t| t.0).collect();
+        assert_eq!(all, vec![4, 3, 2, 1, 0]);
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let drops = Rc::new(Cell::new(0));
+        let vec: InlineVec<Rc<Cell<usize>>, 4> = (0..3).map(|_| drops.clone()).collect();
+        let copy = vec.clone();
+        assert_eq!(Rc::strong_count(&drops), 7);
+        drop(vec);
+        assert_eq!(Rc::strong_count(&drops), 4);
+        drop(copy);
+        assert_eq!(Rc::strong_count(&drops), 1);
+    }
+}
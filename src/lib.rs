@@ -0,0 +1,8 @@
+pub mod aggregate;
+pub mod btrees;
+pub mod byte_tree;
+pub mod inline_vec;
+pub mod multimap;
+pub mod packed;
+pub mod page;
+pub mod slab;
@@ -1,5 +1,4 @@
-use crate::btrees::*;
-pub mod btrees;
+use b_plus_tree::btrees::*;
 
 
 
@@ -12,9 +11,8 @@ fn main() {
 
     test.print_tree(0,1);
 
-    // !!!! Be careful while using delete because if you delete too much keyvalue < 5 the tree will underflow!!!!!
     for i in 1..3 {
-        test.delete(i);
+        test.delete(&i);
     }
     
 
@@ -0,0 +1,154 @@
+use std::fmt::{self, Debug};
+
+use crate::btrees::{BPlusTree, KeyValue, DEFAULT_CAPACITY};
+
+// A B+ tree that keeps any number of values per key, in the order they were inserted.
+//
+// Every entry is stored under its key paired with an insertion number that only grows, so the keys inside the tree
+// are still unique and a run of equal keys sorts by insertion. A run can then spread over any number of leaves:
+// `split` pushes up a (key, number) pair as the separator, which divides a run as cleanly as two different keys,
+// and a lookup for the key goes down to the run's first leaf and follows the leaf chain from there.
+#[derive(Clone)]
+pub struct BPlusMultiMap<K, V, const B: usize = DEFAULT_CAPACITY> {
+    tree: BPlusTree<(K, u64), V, B>,
+    // Insertion number for the next value.
+    next: u64,
+}
+
+impl<K: Ord + Clone, V, const B: usize> Default for BPlusMultiMap<K, V, B> {
+    fn default() -> Self {
+        BPlusMultiMap { tree: BPlusTree::default(), next: 0 }
+    }
+}
+
+impl<K: Ord + Clone, V> BPlusMultiMap<K, V> {
+    pub fn new() -> Self {
+        BPlusMultiMap { tree: BPlusTree::new(), next: 0 }
+    }
+}
+
+impl<K: Ord + Clone, V, const B: usize> BPlusMultiMap<K, V, B> {
+    // See `BPlusTree::with_order`.
+    pub fn with_order(order: usize) -> Self {
+        BPlusMultiMap { tree: BPlusTree::with_order(order), next: 0 }
+    }
+
+    // Adds `value` after the values `key` already has.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tree.insert(KeyValue { key: (key, self.next), value });
+        self.next += 1;
+    }
+
+    // The values of `key`, oldest first.
+    pub fn get(&self, key: &K) -> impl DoubleEndedIterator<Item = &V> + '_ {
+        self.tree.range((key.clone(), 0)..=(key.clone(), u64::MAX)).map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).next().is_some()
+    }
+
+    // Removes the oldest `key` / `value` pair and returns whether there was one.
+    pub fn remove(&mut self, key: &K, value: &V) -> bool where V: PartialEq {
+        let found = self.tree.range((key.clone(), 0)..=(key.clone(), u64::MAX)).find(|(_, v)| *v == value);
+        match found.map(|(stored, _)| stored.clone()) {
+            Some(stored) => self.tree.delete(&stored),
+            None => false,
+        }
+    }
+
+    // Removes every value of `key` and returns how many there were.
+    pub fn remove_all(&mut self, key: &K) -> usize {
+        self.tree.remove_range((key.clone(), 0)..=(key.clone(), u64::MAX))
+    }
+
+    // Number of key / value pairs.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+
+    // All pairs by key, and the values of a key in insertion order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + '_ {
+        self.tree.iter().map(|((key, _), value)| (key, value))
+    }
+}
+
+impl<K: Ord + Clone + Debug, V: Debug, const B: usize> Debug for BPlusMultiMap<K, V, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BPlusMultiMap;
+    use std::collections::BTreeMap;
+
+    // Puts the same mix of runs into the multimap and into a map of value lists.
+    fn filled(order: usize) -> (BPlusMultiMap<u32, u32, 6>, BTreeMap<u32, Vec<u32>>) {
+        let mut multimap = BPlusMultiMap::with_order(order);
+        let mut model: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        // Key 5 gets a run much longer than a leaf, interleaved with its neighbours so its leaves are split in turn.
+        for i in 0..200 {
+            let key = [5, 5, 5, 4, 6, 5, i % 10][i as usize % 7];
+            multimap.insert(key, i);
+            model.entry(key).or_default().push(i);
+        }
+        (multimap, model)
+    }
+
+    fn assert_same(multimap: &BPlusMultiMap<u32, u32, 6>, model: &BTreeMap<u32, Vec<u32>>) {
+        let pairs: Vec<(u32, u32)> = model.iter().flat_map(|(&key, values)| values.iter().map(move |&value| (key, value))).collect();
+        assert!(multimap.iter().map(|(&key, &value)| (key, value)).eq(pairs.iter().copied()));
+        assert_eq!(multimap.len(), pairs.len());
+        for key in 0..12 {
+            let values = model.get(&key).map(Vec::as_slice).unwrap_or_default();
+            assert!(multimap.get(&key).copied().eq(values.iter().copied()), "values of {}", key);
+            assert!(multimap.get(&key).rev().copied().eq(values.iter().rev().copied()), "values of {} from the back", key);
+            assert_eq!(multimap.contains_key(&key), !values.is_empty());
+        }
+    }
+
+    #[test]
+    fn runs_spanning_several_leaves_keep_insertion_order() {
+        for order in [3, 4] {
+            let (mut multimap, mut model) = filled(order);
+            assert!(model[&5].len() > 10 * order, "the run of 5 covers many leaves");
+            assert_same(&multimap, &model);
+
+            // Single pairs out of the start, the middle and the end of the run.
+            for value in [0, 98, 196, 1000] {
+                let expected = model[&5].contains(&value);
+                assert_eq!(multimap.remove(&5, &value), expected);
+                model.get_mut(&5).unwrap().retain(|&v| v != value);
+            }
+            assert_same(&multimap, &model);
+
+            // New values of a key go behind the old ones, even where the run was cut by separators.
+            for value in 500..520 {
+                multimap.insert(5, value);
+                model.get_mut(&5).unwrap().push(value);
+            }
+            assert_same(&multimap, &model);
+            // With the same pair twice the older one goes first.
+            multimap.insert(5, 7);
+            let run = model.get_mut(&5).unwrap();
+            run.push(7);
+            assert!(multimap.remove(&5, &7));
+            run.remove(run.iter().position(|&v| v == 7).unwrap());
+            assert_same(&multimap, &model);
+
+            assert_eq!(multimap.remove_all(&5), model.remove(&5).unwrap().len());
+            assert_eq!(multimap.remove_all(&5), 0);
+            assert_same(&multimap, &model);
+        }
+    }
+}
@@ -0,0 +1,522 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+
+use crate::btrees::{BPlusTree, Entry, KeyValue};
+
+// Leaf size used by `PackedTree::new`.
+pub const DEFAULT_LEAF_SIZE: usize = 256;
+
+// Integer keys, packed by their `u64` image. The image has to keep the order of the keys and map the smallest key
+// to 0.
+pub trait PackedKey: Copy + Ord {
+    fn to_u64(self) -> u64;
+    fn from_u64(value: u64) -> Self;
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {$(
+        impl PackedKey for $t {
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_u64(value: u64) -> Self {
+                value as $t
+            }
+        }
+    )*};
+}
+
+// Signed keys are shifted up by their type's minimum, which keeps their order and their distances.
+macro_rules! signed_key {
+    ($($t:ty),*) => {$(
+        impl PackedKey for $t {
+            fn to_u64(self) -> u64 {
+                (self as i64).wrapping_sub(<$t>::MIN as i64) as u64
+            }
+
+            fn from_u64(value: u64) -> Self {
+                (value as i64).wrapping_add(<$t>::MIN as i64) as $t
+            }
+        }
+    )*};
+}
+
+unsigned_key!(u8, u16, u32, u64, usize);
+signed_key!(i8, i16, i32, i64, isize);
+
+// The keys of a leaf, sorted and unique, stored as the first key plus the distance of every key from it, bit packed
+// at the width of the largest distance (frame of reference). A run that is dense enough is stored as a bitmap over
+// `base..=last` instead, one bit per possible key, whenever that takes fewer bits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PackedKeys {
+    Deltas { base: u64, width: u32, len: usize, words: Vec<u64> },
+    Bitmap { base: u64, len: usize, words: Vec<u64> },
+}
+
+// Reads the `width` bits that start at bit `at`.
+fn read_bits(words: &[u64], at: usize, width: u32) -> u64 {
+    if width == 0 {
+        return 0
+    }
+    let (word, bit) = (at / 64, at % 64);
+    let mut value = words[word] >> bit;
+    if bit + width as usize > 64 {
+        value |= words[word + 1] << (64 - bit);
+    }
+    if width == 64 { value } else { value & ((1 << width) - 1) }
+}
+
+fn write_bits(words: &mut [u64], at: usize, width: u32, value: u64) {
+    if width == 0 {
+        return
+    }
+    let (word, bit) = (at / 64, at % 64);
+    words[word] |= value << bit;
+    if bit + width as usize > 64 {
+        words[word + 1] |= value >> (64 - bit);
+    }
+}
+
+impl PackedKeys {
+    pub fn encode(keys: &[u64]) -> Self {
+        let (Some(&base), Some(&last)) = (keys.first(), keys.last()) else {
+            return PackedKeys::Deltas { base: 0, width: 0, len: 0, words: Vec::new() }
+        };
+        let len = keys.len();
+        let span = last - base;
+        let width = u64::BITS - span.leading_zeros();
+        // The bitmap needs `span + 1` bits, the deltas `len * width`.
+        if span < len as u64 * width as u64 {
+            let mut words = vec![0; span as usize / 64 + 1];
+            for &key in keys {
+                let offset = (key - base) as usize;
+                words[offset / 64] |= 1 << (offset % 64);
+            }
+            return PackedKeys::Bitmap { base, len, words }
+        }
+        let mut words = vec![0; (len * width as usize).div_ceil(64)];
+        for (i, &key) in keys.iter().enumerate() {
+            write_bits(&mut words, i * width as usize, width, key - base);
+        }
+        PackedKeys::Deltas { base, width, len, words }
+    }
+
+    pub fn decode(&self) -> Vec<u64> {
+        self.iter().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            PackedKeys::Deltas { len, .. } | PackedKeys::Bitmap { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Bytes the encoded keys take: the base and the packed words.
+    pub fn size(&self) -> usize {
+        match self {
+            PackedKeys::Deltas { words, .. } | PackedKeys::Bitmap { words, .. } => 8 + 8 * words.len(),
+        }
+    }
+
+    // Key `i`, decoded on its own. A bitmap has to count the set bits up to it.
+    pub fn get(&self, i: usize) -> u64 {
+        assert!(i < self.len(), "index {} out of {} keys", i, self.len());
+        match self {
+            PackedKeys::Deltas { base, width, words, .. } => base + read_bits(words, i * *width as usize, *width),
+            PackedKeys::Bitmap { base, words, .. } => {
+                let mut rest = i;
+                for (w, &word) in words.iter().enumerate() {
+                    let ones = word.count_ones() as usize;
+                    if rest < ones {
+                        let mut bits = word;
+                        for _ in 0..rest {
+                            bits &= bits - 1;
+                        }
+                        return base + (w * 64) as u64 + bits.trailing_zeros() as u64
+                    }
+                    rest -= ones;
+                }
+                unreachable!("bitmap holds fewer keys than its length")
+            },
+        }
+    }
+
+    // Like `slice::binary_search`. A bitmap answers with the number of set bits below the key instead.
+    pub fn search(&self, key: u64) -> Result<usize, usize> {
+        match self {
+            PackedKeys::Deltas { len, .. } => {
+                let (mut low, mut high) = (0, *len);
+                while low < high {
+                    let middle = (low + high) / 2;
+                    match self.get(middle).cmp(&key) {
+                        Ordering::Less => low = middle + 1,
+                        Ordering::Greater => high = middle,
+                        Ordering::Equal => return Ok(middle),
+                    }
+                }
+                Err(low)
+            },
+            PackedKeys::Bitmap { base, len, words } => {
+                if key < *base {
+                    return Err(0)
+                }
+                let offset = key - base;
+                if offset >= 64 * words.len() as u64 {
+                    return Err(*len)
+                }
+                let (word, bit) = (offset as usize / 64, offset % 64);
+                let below = words[..word].iter().map(|w| w.count_ones() as usize).sum::<usize>()
+                    + (words[word] & ((1 << bit) - 1)).count_ones() as usize;
+                if words[word] >> bit & 1 == 1 { Ok(below) } else { Err(below) }
+            },
+        }
+    }
+
+    pub fn iter(&self) -> PackedIter<'_> {
+        let bits = match self {
+            PackedKeys::Bitmap { words, .. } => words[0],
+            PackedKeys::Deltas { .. } => 0,
+        };
+        PackedIter { keys: self, index: 0, word: 0, bits }
+    }
+}
+
+// Iterator over the keys of `PackedKeys` in order, decoding them one at a time.
+pub struct PackedIter<'a> {
+    keys: &'a PackedKeys,
+    index: usize,
+    // The bitmap word being walked and its set bits that are still to come.
+    word: usize,
+    bits: u64,
+}
+
+impl Iterator for PackedIter<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.index == self.keys.len() {
+            return None
+        }
+        self.index += 1;
+        match self.keys {
+            PackedKeys::Deltas { base, width, words, .. } => Some(base + read_bits(words, (self.index - 1) * *width as usize, *width)),
+            PackedKeys::Bitmap { base, words, .. } => {
+                while self.bits == 0 {
+                    self.word += 1;
+                    self.bits = words[self.word];
+                }
+                let offset = self.word * 64 + self.bits.trailing_zeros() as usize;
+                self.bits &= self.bits - 1;
+                Some(base + offset as u64)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Leaf<V> {
+    keys: PackedKeys,
+    values: Vec<V>,
+}
+
+// A map over integer keys whose leaves keep their keys packed (see `PackedKeys`), for dense ids that would otherwise
+// spend a whole integer per entry.
+//
+// The leaves hang off a `BPlusTree` that works as the upper levels: each leaf is stored under the lowest key it may
+// hold, and the first one under the smallest key there is, so `floor` finds the leaf of any key. A lookup searches
+// the packed keys in place. An insert or delete decodes the leaf's keys, changes them and encodes them again; a leaf
+// that grows past `leaf_size` is split in two halves that are encoded on their own, and one that drops below a
+// quarter of it is merged with a neighbour or evened out with it.
+#[derive(Clone)]
+pub struct PackedTree<K: PackedKey, V> {
+    index: BPlusTree<K, Leaf<V>>,
+    leaf_size: usize,
+    len: usize,
+}
+
+impl<K: PackedKey, V> Default for PackedTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: PackedKey, V> PackedTree<K, V> {
+    pub fn new() -> Self {
+        Self::with_leaf_size(DEFAULT_LEAF_SIZE)
+    }
+
+    // `leaf_size` is the most keys a leaf holds.
+    pub fn with_leaf_size(leaf_size: usize) -> Self {
+        assert!(leaf_size >= 4, "leaves must hold at least 4 keys");
+        PackedTree { index: BPlusTree::new(), leaf_size, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Bytes all leaves spend on their keys.
+    pub fn key_bytes(&self) -> usize {
+        self.index.iter().map(|(_, leaf)| leaf.keys.size()).sum()
+    }
+
+    fn leaf_mut(&mut self, at: K) -> &mut Leaf<V> {
+        match self.index.entry(at) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(_) => panic!("leaf is missing from the index"),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let (_, leaf) = self.index.floor(key)?;
+        leaf.keys.search(key.to_u64()).ok().map(|i| &leaf.values[i])
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    // Returns false when the key was already present; its value is overwritten in that case.
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let Some(at) = self.index.floor(&key).map(|(at, _)| *at) else {
+            let leaf = Leaf { keys: PackedKeys::encode(&[key.to_u64()]), values: vec![value] };
+            self.index.insert(KeyValue { key: K::from_u64(0), value: leaf });
+            self.len += 1;
+            return true
+        };
+        let leaf_size = self.leaf_size;
+        let leaf = self.leaf_mut(at);
+        let index = match leaf.keys.search(key.to_u64()) {
+            Ok(index) => {
+                leaf.values[index] = value;
+                return false
+            },
+            Err(index) => index,
+        };
+        let mut keys = leaf.keys.decode();
+        keys.insert(index, key.to_u64());
+        leaf.values.insert(index, value);
+        let right = if keys.len() > leaf_size {
+            Some(Self::split(leaf, &keys))
+        } else {
+            leaf.keys = PackedKeys::encode(&keys);
+            None
+        };
+        self.len += 1;
+        if let Some((at, right)) = right {
+            self.index.insert(KeyValue { key: at, value: right });
+        }
+        true
+    }
+
+    // Keeps the lower half of `keys` in `leaf` and returns the upper half as a new leaf, with the key to store it under.
+    fn split(leaf: &mut Leaf<V>, keys: &[u64]) -> (K, Leaf<V>) {
+        let half = keys.len() / 2;
+        let values = leaf.values.split_off(half);
+        leaf.keys = PackedKeys::encode(&keys[..half]);
+        (K::from_u64(keys[half]), Leaf { keys: PackedKeys::encode(&keys[half..]), values })
+    }
+
+    pub fn delete(&mut self, key: &K) -> bool {
+        let Some(at) = self.index.floor(key).map(|(at, _)| *at) else { return false };
+        let leaf_size = self.leaf_size;
+        let leaf = self.leaf_mut(at);
+        let Ok(index) = leaf.keys.search(key.to_u64()) else { return false };
+        let mut keys = leaf.keys.decode();
+        keys.remove(index);
+        leaf.values.remove(index);
+        leaf.keys = PackedKeys::encode(&keys);
+        self.len -= 1;
+        if keys.len() < leaf_size / 4 {
+            self.rebalance(at);
+        }
+        true
+    }
+
+    // Fixes up the leaf stored under `at` after it dropped below a quarter of `leaf_size`. It is paired with the next
+    // leaf, or with the one before it when it is the last, and the right one of the two is merged into the left one.
+    // When that makes too many keys for one leaf, the two are split in halves again. The only leaf is dropped once
+    // it is empty.
+    fn rebalance(&mut self, at: K) {
+        let (left, right) = match (self.index.predecessor(&at), self.index.successor(&at)) {
+            (_, Some((next, _))) => (at, *next),
+            (Some((prev, _)), None) => (*prev, at),
+            (None, None) => {
+                if self.len == 0 {
+                    self.index.clear();
+                }
+                return
+            },
+        };
+        let right_leaf = match self.index.entry(right) {
+            Entry::Occupied(entry) => entry.remove(),
+            Entry::Vacant(_) => panic!("leaf is missing from the index"),
+        };
+        let leaf_size = self.leaf_size;
+        let leaf = self.leaf_mut(left);
+        let mut keys = leaf.keys.decode();
+        keys.extend(right_leaf.keys.iter());
+        leaf.values.extend(right_leaf.values);
+        if keys.len() <= leaf_size {
+            leaf.keys = PackedKeys::encode(&keys);
+            return
+        }
+        let (at, right_leaf) = Self::split(leaf, &keys);
+        self.index.insert(KeyValue { key: at, value: right_leaf });
+    }
+
+    // All entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (K, &V)> + '_ {
+        self.index.iter().flat_map(|(_, leaf)| leaf.keys.iter().map(K::from_u64).zip(&leaf.values))
+    }
+}
+
+impl<K: PackedKey + Debug, V: Debug> Debug for PackedTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    // Encodes `keys` and checks that every way of reading them back agrees.
+    fn round_trip(keys: &[u64]) -> PackedKeys {
+        let packed = PackedKeys::encode(keys);
+        assert_eq!(packed.decode(), keys);
+        assert_eq!(packed.len(), keys.len());
+        for (i, &key) in keys.iter().enumerate() {
+            assert_eq!(packed.get(i), key);
+            assert_eq!(packed.search(key), Ok(i));
+            if key > 0 && (i == 0 || keys[i - 1] != key - 1) {
+                assert_eq!(packed.search(key - 1), Err(i));
+            }
+        }
+        if let Some(&last) = keys.last().filter(|&&last| last < u64::MAX) {
+            assert_eq!(packed.search(last + 1), Err(keys.len()));
+        }
+        packed
+    }
+
+    fn is_bitmap(packed: &PackedKeys) -> bool {
+        matches!(packed, PackedKeys::Bitmap { .. })
+    }
+
+    #[test]
+    fn picks_the_smaller_encoding() {
+        assert!(round_trip(&[]).is_empty());
+        // Two keys five apart take six bits as deltas of width three and six as a bitmap, six apart seven.
+        assert!(is_bitmap(&round_trip(&[10, 15])));
+        assert!(!is_bitmap(&round_trip(&[10, 16])));
+        assert!(is_bitmap(&round_trip(&(1000..1300).collect::<Vec<_>>())));
+        assert!(is_bitmap(&round_trip(&(0..500).filter(|key| key % 3 != 0).collect::<Vec<_>>())));
+        assert!(!is_bitmap(&round_trip(&(0..50).map(|key| key * 1000).collect::<Vec<_>>())));
+        // Every key equal to the base: deltas of width zero.
+        assert_eq!(round_trip(&[42]), PackedKeys::Deltas { base: 42, width: 0, len: 1, words: Vec::new() });
+
+        // Thinning out a dense run turns it into deltas once the gaps cost more than the bits.
+        let mut keys: Vec<u64> = (0..256).collect();
+        let mut switched = false;
+        while keys.len() > 2 {
+            keys = keys.iter().copied().step_by(2).collect();
+            let packed = round_trip(&keys);
+            let (len, span) = (keys.len() as u64, keys[keys.len() - 1] - keys[0]);
+            assert_eq!(is_bitmap(&packed), span < len * (u64::BITS - span.leading_zeros()) as u64);
+            switched |= !is_bitmap(&packed);
+        }
+        assert!(switched);
+    }
+
+    #[test]
+    fn widest_keys_pack() {
+        round_trip(&[0, u64::MAX]);
+        round_trip(&[u64::MAX - 2, u64::MAX - 1, u64::MAX]);
+        round_trip(&[1, 1 << 63, u64::MAX]);
+        round_trip(&(0..70).map(|i| u64::MAX - i * (u64::MAX / 70)).rev().collect::<Vec<_>>());
+    }
+
+    fn assert_same<K: PackedKey + Debug>(tree: &PackedTree<K, u32>, map: &BTreeMap<K, u32>) {
+        assert_eq!(tree.len(), map.len());
+        assert!(tree.iter().map(|(key, &value)| (key, value)).eq(map.iter().map(|(&key, &value)| (key, value))));
+        for (key, value) in map {
+            assert_eq!(tree.get(key), Some(value), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn signed_keys_and_the_ends_of_their_range() {
+        let mut tree = PackedTree::with_leaf_size(4);
+        let mut map = BTreeMap::new();
+        let keys = [i64::MIN, i64::MIN + 1, -1_000_000, -2, -1, 0, 1, 2, 999, i64::MAX - 1, i64::MAX];
+        for (i, &key) in keys.iter().enumerate().rev() {
+            assert!(tree.insert(key, i as u32));
+            map.insert(key, i as u32);
+        }
+        assert_same(&tree, &map);
+        assert!(!tree.contains_key(&-3) && !tree.contains_key(&3));
+        assert!(!tree.insert(i64::MIN, 7));
+        map.insert(i64::MIN, 7);
+        for key in [0, i64::MAX, -1] {
+            assert!(tree.delete(&key));
+            map.remove(&key);
+            assert_same(&tree, &map);
+        }
+
+        let mut tree = PackedTree::with_leaf_size(16);
+        let mut map = BTreeMap::new();
+        for key in (i8::MIN..=i8::MAX).rev() {
+            tree.insert(key, key as u32);
+            map.insert(key, key as u32);
+        }
+        assert_same(&tree, &map);
+
+        let mut tree = PackedTree::with_leaf_size(4);
+        let mut map = BTreeMap::new();
+        for key in [u64::MAX, 0, u64::MAX - 1, 1 << 63, 5] {
+            tree.insert(key, key as u32);
+            map.insert(key, key as u32);
+        }
+        assert_same(&tree, &map);
+        assert!(tree.delete(&u64::MAX));
+        map.remove(&u64::MAX);
+        assert_same(&tree, &map);
+    }
+
+    #[test]
+    fn leaves_switch_encoding_as_they_change() {
+        let mut tree = PackedTree::with_leaf_size(64);
+        let mut map = BTreeMap::new();
+        for key in 0..1000u32 {
+            tree.insert(key, key);
+            map.insert(key, key);
+        }
+        assert!(tree.index.iter().all(|(_, leaf)| is_bitmap(&leaf.keys)), "dense ids are stored as bitmaps");
+        let dense = tree.key_bytes();
+        // Leaving every hundredth key makes the runs too sparse for a bitmap, and the leaves merge.
+        for key in (0..1000).filter(|key| key % 100 != 0) {
+            assert!(tree.delete(&key));
+            map.remove(&key);
+        }
+        assert_same(&tree, &map);
+        assert!(tree.index.iter().all(|(_, leaf)| !is_bitmap(&leaf.keys)));
+        assert!(tree.key_bytes() < dense);
+        // Filling a gap back in makes its leaf dense again.
+        for key in 200..300 {
+            tree.insert(key, key);
+            map.insert(key, key);
+        }
+        assert_same(&tree, &map);
+        assert!(tree.index.iter().any(|(_, leaf)| is_bitmap(&leaf.keys)));
+    }
+}
@@ -0,0 +1,494 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use crate::slab::NodeId;
+
+// A node of a `BytesTree` laid out as a slotted page: a fixed-size block of bytes, as it would be stored on disk.
+//
+//   | header | prefix | slot 0 | slot 1 | ... -> free space <- ... | cell 1 | cell 0 |
+//
+// The keys of a page are prefix compressed: the prefix they all share is stored once after the header and the cells
+// only keep the rest of their keys. The slots after the prefix are the offsets of the cells in key order. The cells are written from the end of
+// the page towards the front, and the free space pointer in the header marks where the last one starts. A cell that
+// is removed leaves a hole behind; the holes are squeezed out once a new cell only fits without them.
+//
+// Header, all numbers little endian:
+//   0      kind, LEAF, INTERNAL or OVERFLOW
+//   2..4   number of cells
+//   4..6   free space pointer, where the cell area starts
+//   6..8   bytes taken by live cells
+//   8..12  leaf: the next leaf; internal: the rightmost child; overflow: the next page of the chain
+//   12..16 leaf: the previous leaf
+//   16..18 length of the shared key prefix
+//
+// Leaf cell:     key length u16 | value length u16 | key | value
+// Internal cell: child u32 | key length u16 | key
+// Cells are handed in and out with their whole keys, the page strips and restores the prefix itself.
+// An internal page with n cells has n + 1 children: the child of cell i holds the keys below key i, and the rightmost
+// child in the header the keys from the last key on.
+//
+// A value too big for its leaf spills into a chain of overflow pages, which hold its bytes right after the header
+// and their own length in the live bytes field. Its leaf cell then has the SPILLED bit set in the value length and
+// keeps only the value's total length u32 and the first page of the chain u32 in place of the value.
+#[derive(Clone, Debug)]
+pub struct Page {
+    buf: Box<[u8]>,
+}
+
+pub const PAGE_HEADER: usize = 18;
+// Bytes each cell takes in the slot array.
+pub const SLOT_SIZE: usize = 2;
+// Offsets are 16 bits wide, which caps the page size.
+pub const MAX_PAGE_SIZE: usize = 1 << 15;
+
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+const OVERFLOW: u8 = 3;
+// Marks a leaf cell whose value lives in overflow pages. Lengths stay below the page size, so the bit is free.
+const SPILLED: usize = 1 << 15;
+// Stands for a missing leaf link.
+const NONE: u32 = u32::MAX;
+
+// The cell an entry takes in a leaf.
+pub fn leaf_cell(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(4 + key.len() + value.len());
+    cell.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    cell.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    cell.extend_from_slice(key);
+    cell.extend_from_slice(value);
+    cell
+}
+
+// The leaf cell of an entry whose value of `len` bytes was spilled into the overflow chain starting at `first`.
+pub fn spilled_cell(key: &[u8], len: usize, first: NodeId) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(12 + key.len());
+    cell.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    cell.extend_from_slice(&((SPILLED | 8) as u16).to_le_bytes());
+    cell.extend_from_slice(key);
+    cell.extend_from_slice(&(len as u32).to_le_bytes());
+    cell.extend_from_slice(&first.to_le_bytes());
+    cell
+}
+
+// The cell of a child and the key above it in an internal page.
+pub fn internal_cell(child: NodeId, key: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(6 + key.len());
+    cell.extend_from_slice(&child.to_le_bytes());
+    cell.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    cell.extend_from_slice(key);
+    cell
+}
+
+// The key of a cell that is not in a page yet, see `Page::cells`.
+pub fn cell_key(cell: &[u8], is_leaf: bool) -> &[u8] {
+    let (at, len) = if is_leaf { (4, &cell[0..2]) } else { (6, &cell[4..6]) };
+    &cell[at..at + u16::from_le_bytes([len[0], len[1]]) as usize]
+}
+
+// The child of an internal cell that is not in a page yet.
+pub fn cell_child(cell: &[u8]) -> NodeId {
+    u32::from_le_bytes(cell[0..4].try_into().unwrap())
+}
+
+// Length of the prefix `a` and `b` share.
+pub fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+// Bytes `cells` take in a page of their own: the header, the prefix their sorted keys share, the slots and the cells
+// without that prefix.
+pub fn occupancy(cells: &[Vec<u8>], is_leaf: bool) -> usize {
+    let prefix = match (cells.first(), cells.last()) {
+        (Some(first), Some(last)) => common_prefix(cell_key(first, is_leaf), cell_key(last, is_leaf)),
+        _ => 0,
+    };
+    PAGE_HEADER + prefix + cells.iter().map(|cell| SLOT_SIZE + cell.len() - prefix).sum::<usize>()
+}
+
+impl Page {
+    pub fn new(size: usize, is_leaf: bool) -> Self {
+        assert!(size <= MAX_PAGE_SIZE, "pages can be at most {} bytes", MAX_PAGE_SIZE);
+        let mut page = Page { buf: vec![0; size].into_boxed_slice() };
+        page.buf[0] = if is_leaf { LEAF } else { INTERNAL };
+        page.set_u16(4, size);
+        page.set_u32(8, NONE);
+        page.set_u32(12, NONE);
+        page
+    }
+
+    // A page of an overflow chain holding `data`, followed by the page `next`.
+    pub fn overflow(size: usize, data: &[u8], next: Option<NodeId>) -> Self {
+        let mut page = Page::new(size, false);
+        page.buf[0] = OVERFLOW;
+        page.buf[PAGE_HEADER..PAGE_HEADER + data.len()].copy_from_slice(data);
+        page.set_u16(6, data.len());
+        page.set_next(next);
+        page
+    }
+
+    // The piece of a value an overflow page holds.
+    pub fn data(&self) -> &[u8] {
+        debug_assert!(self.buf[0] == OVERFLOW);
+        &self.buf[PAGE_HEADER..PAGE_HEADER + self.live()]
+    }
+
+    fn get_u16(&self, at: usize) -> usize {
+        u16::from_le_bytes([self.buf[at], self.buf[at + 1]]) as usize
+    }
+
+    fn set_u16(&mut self, at: usize, value: usize) {
+        // The page size is capped, so every offset and length fits; a full page of 2^15 bytes still does.
+        self.buf[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes());
+    }
+
+    fn get_u32(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.buf[at..at + 4].try_into().unwrap())
+    }
+
+    fn set_u32(&mut self, at: usize, value: u32) {
+        self.buf[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn size(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.buf[0] == LEAF
+    }
+
+    // Number of cells.
+    pub fn len(&self) -> usize {
+        self.get_u16(2)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn free_pointer(&self) -> usize {
+        self.get_u16(4)
+    }
+
+    fn live(&self) -> usize {
+        self.get_u16(6)
+    }
+
+    // The part all keys of the page start with.
+    pub fn prefix(&self) -> &[u8] {
+        &self.buf[PAGE_HEADER..self.slots_start()]
+    }
+
+    fn slots_start(&self) -> usize {
+        PAGE_HEADER + self.get_u16(16)
+    }
+
+    // Bytes in use: the header, the prefix, the slots and the live cells. Holes left by removed cells don't count.
+    pub fn used(&self) -> usize {
+        self.slots_start() + SLOT_SIZE * self.len() + self.live()
+    }
+
+    // Bytes that would be in use with `cell` added. A key without the page's prefix makes every key keep more of
+    // its own.
+    pub fn used_with(&self, cell: &[u8]) -> usize {
+        let shared = common_prefix(cell_key(cell, self.is_leaf()), self.prefix());
+        let grown = self.prefix().len() - shared;
+        self.used() - grown + SLOT_SIZE + cell.len() - shared + self.len() * grown
+    }
+
+    fn slot(&self, i: usize) -> usize {
+        self.get_u16(self.slots_start() + SLOT_SIZE * i)
+    }
+
+    fn cell_len(&self, at: usize) -> usize {
+        if self.is_leaf() {
+            4 + self.get_u16(at) + (self.get_u16(at + 2) & !SPILLED)
+        } else {
+            6 + self.get_u16(at + 4)
+        }
+    }
+
+    // Cell `i` as it is stored, without the prefix.
+    fn stored(&self, i: usize) -> &[u8] {
+        let at = self.slot(i);
+        &self.buf[at..at + self.cell_len(at)]
+    }
+
+    // Cell `i` with its whole key.
+    pub fn cell(&self, i: usize) -> Vec<u8> {
+        let stored = self.stored(i);
+        let (head, len_at) = if self.is_leaf() { (4, 0) } else { (6, 4) };
+        let mut cell = Vec::with_capacity(stored.len() + self.prefix().len());
+        cell.extend_from_slice(&stored[..head]);
+        cell.extend_from_slice(self.prefix());
+        cell.extend_from_slice(&stored[head..]);
+        let len = u16::from_le_bytes([stored[len_at], stored[len_at + 1]]) as usize + self.prefix().len();
+        cell[len_at..len_at + 2].copy_from_slice(&(len as u16).to_le_bytes());
+        cell
+    }
+
+    // The part of key `i` after the prefix.
+    fn suffix(&self, i: usize) -> &[u8] {
+        let at = self.slot(i);
+        if self.is_leaf() {
+            &self.buf[at + 4..at + 4 + self.get_u16(at)]
+        } else {
+            &self.buf[at + 6..at + 6 + self.get_u16(at + 4)]
+        }
+    }
+
+    // Key `i`. It only has to be put together when the page has a prefix.
+    pub fn key(&self, i: usize) -> Cow<'_, [u8]> {
+        if self.prefix().is_empty() {
+            Cow::Borrowed(self.suffix(i))
+        } else {
+            Cow::Owned([self.prefix(), self.suffix(i)].concat())
+        }
+    }
+
+    // The value of cell `i` as it is stored in the leaf, which is only the reference for a spilled one.
+    pub fn value(&self, i: usize) -> &[u8] {
+        debug_assert!(self.is_leaf());
+        let at = self.slot(i);
+        let start = at + 4 + self.get_u16(at);
+        &self.buf[start..start + (self.get_u16(at + 2) & !SPILLED)]
+    }
+
+    // The length and the first overflow page of the value of cell `i`, if it was spilled.
+    pub fn spilled(&self, i: usize) -> Option<(usize, NodeId)> {
+        debug_assert!(self.is_leaf());
+        if self.get_u16(self.slot(i) + 2) & SPILLED == 0 {
+            return None
+        }
+        let value = self.value(i);
+        Some((u32::from_le_bytes(value[0..4].try_into().unwrap()) as usize, u32::from_le_bytes(value[4..8].try_into().unwrap())))
+    }
+
+    // Child `i` of an internal page; `len()` is the rightmost one.
+    pub fn child(&self, i: usize) -> NodeId {
+        debug_assert!(!self.is_leaf());
+        if i == self.len() {
+            self.get_u32(8)
+        } else {
+            self.get_u32(self.slot(i))
+        }
+    }
+
+    pub fn set_child(&mut self, i: usize, child: NodeId) {
+        debug_assert!(!self.is_leaf());
+        let at = if i == self.len() { 8 } else { self.slot(i) };
+        self.set_u32(at, child);
+    }
+
+    pub fn next(&self) -> Option<NodeId> {
+        Some(self.get_u32(8)).filter(|&id| id != NONE)
+    }
+
+    pub fn prev(&self) -> Option<NodeId> {
+        Some(self.get_u32(12)).filter(|&id| id != NONE)
+    }
+
+    pub fn set_next(&mut self, next: Option<NodeId>) {
+        self.set_u32(8, next.unwrap_or(NONE));
+    }
+
+    pub fn set_prev(&mut self, prev: Option<NodeId>) {
+        self.set_u32(12, prev.unwrap_or(NONE));
+    }
+
+    // Binary search over the keys, like `slice::binary_search`. Only the suffixes are compared; a key without the
+    // prefix sorts before or after all of them.
+    pub fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let Some(rest) = key.strip_prefix(self.prefix()) else {
+            return Err(if key < self.prefix() { 0 } else { self.len() })
+        };
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.suffix(middle).cmp(rest) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(middle),
+            }
+        }
+        Err(low)
+    }
+
+    // Index of the child of an internal page that covers `key`.
+    pub fn child_index(&self, key: &[u8]) -> usize {
+        match self.search(key) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    // Puts `cell` in at slot `i`. Returns false and leaves the page alone when it doesn't fit. A key without the
+    // page's prefix rewrites the page with the shorter prefix.
+    pub fn insert_cell(&mut self, i: usize, cell: &[u8]) -> bool {
+        if self.used_with(cell) > self.size() {
+            return false
+        }
+        if !cell_key(cell, self.is_leaf()).starts_with(self.prefix()) {
+            let mut cells = self.cells();
+            cells.insert(i, cell.to_vec());
+            self.set_cells(&cells);
+            return true
+        }
+        let len = cell.len() - self.prefix().len();
+        let slots_end = self.slots_start() + SLOT_SIZE * self.len();
+        if self.free_pointer() - slots_end < SLOT_SIZE + len {
+            self.defragment();
+        }
+        let at = self.free_pointer() - len;
+        self.write_cell(at, cell);
+        let slot = self.slots_start() + SLOT_SIZE * i;
+        self.buf.copy_within(slot..slots_end, slot + SLOT_SIZE);
+        self.set_u16(slot, at);
+        self.set_u16(2, self.len() + 1);
+        self.set_u16(4, at);
+        self.set_u16(6, self.live() + len);
+        true
+    }
+
+    // Writes `cell` at `at` without the prefix.
+    fn write_cell(&mut self, at: usize, cell: &[u8]) {
+        let prefix = self.prefix().len();
+        let (head, len_at) = if self.is_leaf() { (4, 0) } else { (6, 4) };
+        self.buf[at..at + head].copy_from_slice(&cell[..head]);
+        let len = u16::from_le_bytes([cell[len_at], cell[len_at + 1]]) as usize - prefix;
+        self.set_u16(at + len_at, len);
+        self.buf[at + head..at + cell.len() - prefix].copy_from_slice(&cell[head + prefix..]);
+    }
+
+    // Drops cell `i`. Its bytes stay behind as a hole until the next defragment.
+    pub fn remove_cell(&mut self, i: usize) {
+        let len = self.cell_len(self.slot(i));
+        let slot = self.slots_start() + SLOT_SIZE * i;
+        let slots_end = self.slots_start() + SLOT_SIZE * self.len();
+        self.buf.copy_within(slot + SLOT_SIZE..slots_end, slot);
+        self.set_u16(2, self.len() - 1);
+        self.set_u16(6, self.live() - len);
+    }
+
+    // Replaces all cells with `cells`, which have to fit, under the longest prefix their keys share.
+    pub fn set_cells(&mut self, cells: &[Vec<u8>]) {
+        let is_leaf = self.is_leaf();
+        assert!(occupancy(cells, is_leaf) <= self.size(), "cells don't fit in the page");
+        let prefix: &[u8] = match (cells.first(), cells.last()) {
+            (Some(first), Some(last)) => {
+                let key = cell_key(first, is_leaf);
+                &key[..common_prefix(key, cell_key(last, is_leaf))]
+            },
+            _ => &[],
+        };
+        self.clear();
+        self.buf[PAGE_HEADER..PAGE_HEADER + prefix.len()].copy_from_slice(prefix);
+        self.set_u16(16, prefix.len());
+        let mut at = self.size();
+        for (i, cell) in cells.iter().enumerate() {
+            at -= cell.len() - prefix.len();
+            self.write_cell(at, cell);
+            self.set_u16(self.slots_start() + SLOT_SIZE * i, at);
+        }
+        self.set_u16(2, cells.len());
+        self.set_u16(4, at);
+        self.set_u16(6, self.size() - at);
+    }
+
+    // Copies of all cells in order with their whole keys, for building other pages from them.
+    pub fn cells(&self) -> Vec<Vec<u8>> {
+        (0..self.len()).map(|i| self.cell(i)).collect()
+    }
+
+    // Removes every cell and the prefix, and keeps the kind and the links.
+    pub fn clear(&mut self) {
+        self.set_u16(2, 0);
+        self.set_u16(4, self.size());
+        self.set_u16(6, 0);
+        self.set_u16(16, 0);
+    }
+
+    // Writes the live cells back to back at the end of the page, so all free space is in one piece.
+    fn defragment(&mut self) {
+        let cells: Vec<Vec<u8>> = (0..self.len()).map(|i| self.stored(i).to_vec()).collect();
+        let mut at = self.size();
+        for (i, cell) in cells.iter().enumerate() {
+            at -= cell.len();
+            self.buf[at..at + cell.len()].copy_from_slice(cell);
+            self.set_u16(self.slots_start() + SLOT_SIZE * i, at);
+        }
+        self.set_u16(4, at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf_cells(keys: &[&str]) -> Vec<Vec<u8>> {
+        keys.iter().map(|key| leaf_cell(key.as_bytes(), key.len().to_string().as_bytes())).collect()
+    }
+
+    fn keys(page: &Page) -> Vec<String> {
+        (0..page.len()).map(|i| String::from_utf8(page.key(i).into_owned()).unwrap()).collect()
+    }
+
+    #[test]
+    fn shared_prefix_is_stored_once() {
+        let urls = ["https://example.com/a/1", "https://example.com/a/22", "https://example.com/a/3", "https://example.com/a/4"];
+        let cells = leaf_cells(&urls);
+        let mut page = Page::new(512, true);
+        page.set_cells(&cells);
+        assert_eq!(page.prefix(), b"https://example.com/a/");
+        assert_eq!(keys(&page), urls);
+        assert_eq!(page.cells(), cells);
+        assert_eq!(page.used(), occupancy(&cells, true));
+        let full: usize = cells.iter().map(|cell| SLOT_SIZE + cell.len()).sum();
+        assert_eq!(page.used(), PAGE_HEADER + full - 3 * page.prefix().len());
+        assert_eq!(page.value(1), b"24");
+
+        assert_eq!(page.search(b"https://example.com/a/3"), Ok(2));
+        assert_eq!(page.search(b"https://example.com/a/25"), Err(2));
+        // Keys without the prefix sort before or after every cell.
+        assert_eq!(page.search(b"https://example.com/"), Err(0));
+        assert_eq!(page.search(b"https://example.com/b"), Err(4));
+    }
+
+    #[test]
+    fn key_without_the_prefix_shortens_it() {
+        let mut page = Page::new(512, true);
+        page.set_cells(&leaf_cells(&["path/to/b", "path/to/c", "path/to/d"]));
+        assert_eq!(page.prefix(), b"path/to/");
+        // A key that fits the prefix goes in without touching the others.
+        assert!(page.insert_cell(1, &leaf_cell(b"path/to/bb", b"x")));
+        assert_eq!(page.prefix(), b"path/to/");
+        let cell = leaf_cell(b"path/a", b"");
+        let mut after = page.cells();
+        after.insert(0, cell.clone());
+        assert_eq!(page.used_with(&cell), occupancy(&after, true));
+        assert!(page.insert_cell(0, &cell));
+        assert_eq!(page.prefix(), b"path/");
+        assert_eq!(keys(&page), ["path/a", "path/to/b", "path/to/bb", "path/to/c", "path/to/d"]);
+        assert_eq!(page.used(), occupancy(&page.cells(), true));
+
+        // Holes left by removed cells are squeezed out when a new cell needs their room.
+        for _ in 0..40 {
+            page.remove_cell(1);
+            assert!(page.insert_cell(1, &leaf_cell(b"path/to/b", &[7; 40])));
+        }
+        assert_eq!(keys(&page), ["path/a", "path/to/b", "path/to/bb", "path/to/c", "path/to/d"]);
+        assert_eq!(page.value(1), [7; 40]);
+    }
+
+    #[test]
+    fn internal_pages_keep_their_children() {
+        let cells: Vec<Vec<u8>> = ["key/10", "key/20", "key/30"].iter().enumerate().map(|(i, key)| internal_cell(i as NodeId + 5, key.as_bytes())).collect();
+        let mut page = Page::new(256, false);
+        page.set_cells(&cells);
+        page.set_child(3, 9);
+        assert_eq!(page.prefix(), b"key/");
+        assert_eq!(page.cells(), cells);
+        assert_eq!((0..=3).map(|i| page.child(i)).collect::<Vec<_>>(), [5, 6, 7, 9]);
+        assert_eq!([page.child_index(b"key/05"), page.child_index(b"key/10"), page.child_index(b"key/25"), page.child_index(b"z")], [0, 1, 2, 3]);
+    }
+}